@@ -1,7 +1,3 @@
-from pandas import read_csv
-import matplotlib.pyplot as plt
-import os
-import glob
 from sys import argv
 import subprocess
 
@@ -12,21 +8,6 @@ if (len(argv) != 3):
 INPUT_FOLDER = argv[1]
 OUTPUT_FOLDER = argv[2]
 
-
-all_files = glob.glob(os.path.join(INPUT_FOLDER, "*.csv"))
-
-batch_size = 10
-
-for i in range(0, len(all_files), batch_size):
-    batch_files = all_files[i:i+batch_size]
-    processes = []
-    for file_path in batch_files:
-        file_name = os.path.basename(file_path)
-        # Execute rust code to generate the result file
-        process = subprocess.Popen(
-            # ['cargo', 'run', '--', file_path, os.path.join(OUTPUT_FOLDER, file_name)])
-            ['./target/release/context-matching', file_path, os.path.join(OUTPUT_FOLDER, file_name)])
-        processes.append(process)
-    for process in processes:
-        process.wait()
-
+# The batch command processes all the files of the folder in parallel
+subprocess.run(
+    ['./target/release/context-matching', 'batch', INPUT_FOLDER, OUTPUT_FOLDER])
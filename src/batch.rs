@@ -0,0 +1,159 @@
+use crate::error::Error;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rayon::prelude::*;
+
+/// A file that could not be processed during a batch run
+#[derive(Debug)]
+pub struct BatchFailure {
+    pub input: PathBuf,
+    pub reason: String,
+}
+
+/// Lists the CSV files to process. `input` is either a directory, in which
+/// case all of its `.csv` files are used, or a glob pattern such as
+/// `data/**/*.csv`.
+pub fn collect_input_files(input: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut input_files: Vec<PathBuf> = if Path::new(input).is_dir() {
+        fs::read_dir(input)
+            .map_err(|e| Error::io(input, e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "csv"))
+            .collect()
+    } else {
+        glob::glob(input)
+            .map_err(|e| Error::Config(format!("invalid glob pattern {}: {}", input, e)))?
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect()
+    };
+    input_files.sort();
+
+    if input_files.is_empty() {
+        return Err(Error::Input(format!("no CSV file found for {}", input)));
+    }
+
+    Ok(input_files)
+}
+
+/// Path of an input file relative to the `input` of
+/// [`collect_input_files`]: the directory, or the part of the glob pattern
+/// before its first wildcard. Results are written at this path under the
+/// output directory, so that files with the same name in different
+/// directories of a recursive glob do not overwrite each other.
+pub fn relative_path(input: &str, input_file: &Path) -> PathBuf {
+    let input = Path::new(input);
+    let base: PathBuf = if input.is_dir() {
+        input.to_path_buf()
+    } else {
+        let literal: PathBuf = input
+            .components()
+            .take_while(|component| {
+                !component
+                    .as_os_str()
+                    .to_string_lossy()
+                    .contains(['*', '?', '['])
+            })
+            .collect();
+        if literal == input {
+            // Not a pattern, a single file
+            literal.parent().map(Path::to_path_buf).unwrap_or_default()
+        } else {
+            literal
+        }
+    };
+
+    match input_file.strip_prefix(&base) {
+        Ok(relative) if relative.components().count() > 0 => relative.to_path_buf(),
+        _ => PathBuf::from(input_file.file_name().unwrap_or_default()),
+    }
+}
+
+/// Progress of a batch run, reported to the caller of [`run_batch`], which
+/// decides how to show it
+#[derive(Debug)]
+pub enum BatchProgress<'a> {
+    /// The thread pool is started
+    Started {
+        nb_of_files: usize,
+        nb_of_threads: usize,
+    },
+    /// `done` files out of `nb_of_files` are processed, the last one being
+    /// `input`, with the summary of its job or the reason of its failure
+    FileDone {
+        done: usize,
+        nb_of_files: usize,
+        input: &'a Path,
+        result: &'a Result<String, String>,
+    },
+}
+
+/// Runs `job` on every input file on a work-stealing thread pool and calls
+/// `progress` when the pool is started and whenever a file is done, from the
+/// thread of the file. A file whose job fails, or panics, is recorded as a
+/// failure and does not stop the other files. `nb_of_threads` set to 0 uses
+/// one thread per available core.
+pub fn run_batch<F, P>(
+    input_files: &[PathBuf],
+    nb_of_threads: usize,
+    job: F,
+    progress: P,
+) -> Result<Vec<BatchFailure>, Error>
+where
+    F: Fn(&Path) -> Result<String, Error> + Sync,
+    P: Fn(&BatchProgress) + Sync,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(nb_of_threads)
+        .build()
+        .map_err(|e| Error::Config(format!("cannot start {} threads: {}", nb_of_threads, e)))?;
+
+    let nb_of_files = input_files.len();
+    let nb_done = AtomicUsize::new(0);
+
+    progress(&BatchProgress::Started {
+        nb_of_files,
+        nb_of_threads: pool.current_num_threads(),
+    });
+
+    let mut failures: Vec<BatchFailure> = pool.install(|| {
+        input_files
+            .par_iter()
+            .filter_map(|input| {
+                let result = match panic::catch_unwind(AssertUnwindSafe(|| job(input))) {
+                    Ok(result) => result.map_err(|e| e.to_string()),
+                    Err(panic) => Err(panic_message(panic.as_ref())),
+                };
+
+                let done = nb_done.fetch_add(1, Ordering::SeqCst) + 1;
+                progress(&BatchProgress::FileDone {
+                    done,
+                    nb_of_files,
+                    input,
+                    result: &result,
+                });
+
+                result.err().map(|reason| BatchFailure {
+                    input: input.clone(),
+                    reason,
+                })
+            })
+            .collect()
+    });
+    failures.sort_by(|a, b| a.input.cmp(&b.input));
+
+    Ok(failures)
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        format!("panicked: {}", message)
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        format!("panicked: {}", message)
+    } else {
+        String::from("panicked")
+    }
+}
@@ -0,0 +1,396 @@
+use clap::{Args, Parser, Subcommand};
+use context_matching::config::{
+    CollapsePolicy, Decoder, DistributionFamily, GapPolicy, Point, ResamplingScheme, RunConfig,
+    ShorePenaltyConfig, ShoreRange, SpeedDistribution,
+};
+use context_matching::error::Error;
+use context_matching::observation::ParticleContextType;
+use context_matching::segment::SegmentFormat;
+
+/// Context matching for fishing trajectories with particle filtering.
+#[derive(Debug, Parser)]
+#[command(name = "context-matching", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the particle filter on one trajectory file
+    Run(RunArgs),
+    /// Run the particle filter on many trajectory files in parallel
+    Batch(BatchArgs),
+    /// Score result files against the labels of their input files: segment
+    /// purity and coverage, per-context precision and recall, confusion matrix
+    Evaluate(EvaluateArgs),
+    /// Print the Markov graph used for the context transitions
+    InspectGraph(InspectGraphArgs),
+    /// Learn the Markov graph from the labels of trajectory files
+    TrainGraph(TrainGraphArgs),
+    /// Fit the speed distributions and heading jitter of each context to the
+    /// labels of trajectory files and write them into a run configuration
+    FitMotion(FitMotionArgs),
+    /// Generate labelled trajectories with the Markov graph and the motion
+    /// models of a run configuration
+    Simulate(SimulateArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Input AIS CSV file
+    pub input: String,
+    /// Output result CSV file
+    pub output: String,
+    /// Record the particle memories into this CSV file, with the
+    /// particle-filter decoder only
+    #[arg(long)]
+    pub history: Option<String>,
+    /// Input contains several vessels: group the records by `id` and filter
+    /// each vessel separately. Results are written to a single file with an
+    /// additional `id` column unless --split-output is given
+    #[arg(long)]
+    pub by_vessel: bool,
+    /// With --by-vessel, treat OUTPUT as a directory and write one result
+    /// file per vessel, named `<id>.csv`
+    #[arg(long, requires = "by_vessel")]
+    pub split_output: bool,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    /// Directory containing the input AIS CSV files, or a glob pattern such
+    /// as 'data/**/*.csv'
+    pub input: String,
+    /// Directory where the result files are written, at the same path
+    /// relative to the input directory or to the glob pattern
+    pub output_dir: String,
+    /// Number of worker threads, 0 uses one thread per core
+    #[arg(long, default_value_t = 0)]
+    pub threads: usize,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct EvaluateArgs {
+    /// Labelled input AIS CSV file, or the directory of the input files of the
+    /// result files, with the same file names
+    pub input: String,
+    /// Result CSV file produced by `run`, or a directory of result files or a
+    /// glob pattern. The vessels of `--by-vessel` results are matched with the
+    /// input records of the same `id`
+    pub result: String,
+    /// Run configuration defining the context states and their labels
+    #[arg(long)]
+    pub config: Option<String>,
+    /// Write the report to this JSON file
+    #[arg(long)]
+    pub report: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectGraphArgs {
+    /// Markov graph file
+    #[arg(default_value = "src/graph.txt")]
+    pub graph: String,
+}
+
+#[derive(Debug, Args)]
+pub struct TrainGraphArgs {
+    /// Directory containing the labelled AIS CSV files, or a glob pattern.
+    /// Files may contain several vessels
+    pub input: String,
+    /// Output Markov graph file
+    pub output: String,
+    /// Compute the transition probabilities over this amount of time, using
+    /// the exit rate of each context per unit of `time_gap`, instead of per
+    /// observation
+    #[arg(long)]
+    pub time_step: Option<f64>,
+    /// Write the dwell time statistics of each context to this CSV file
+    #[arg(long)]
+    pub dwell_output: Option<String>,
+    /// Run configuration defining the context states and their labels
+    #[arg(long)]
+    pub config: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct FitMotionArgs {
+    /// Directory containing the labelled AIS CSV files, or a glob pattern.
+    /// Files may contain several vessels
+    pub input: String,
+    /// Output run configuration file (TOML, or JSON with a .json extension)
+    pub output: String,
+    /// Family of the speed distributions: normal, log-normal or gamma. By
+    /// default, the family that fits the data best is used for each context
+    #[arg(long)]
+    pub family: Option<DistributionFamily>,
+    /// Run configuration to start from, its other parameters are kept
+    #[arg(long)]
+    pub config: Option<String>,
+    /// Fit the contexts of this gear profile instead of the contexts of the
+    /// run, e.g. on the trajectories of the vessels of a gear type
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct SimulateArgs {
+    /// Output AIS CSV file
+    pub output: String,
+    /// Treat OUTPUT as a directory and write one file per trajectory, named
+    /// `<id>.csv`
+    #[arg(long)]
+    pub split_output: bool,
+    /// Run configuration defining the context states, their motion models
+    /// and the Markov graph
+    #[arg(long)]
+    pub config: Option<String>,
+    /// Simulate the vessels of this gear profile, written in the `gear`
+    /// column
+    #[arg(long)]
+    pub gear: Option<String>,
+    /// Number of trajectories
+    #[arg(long, default_value_t = 1)]
+    pub trajectories: usize,
+    /// Number of records of each trajectory
+    #[arg(long, default_value_t = 240)]
+    pub observations: usize,
+    /// Time between two records, in seconds
+    #[arg(long, default_value_t = 60.0)]
+    pub interval: f64,
+    /// Standard deviation of the noise added to the recorded positions
+    #[arg(long, default_value_t = 0.0)]
+    pub position_noise: f64,
+    /// Probability that a record is followed by a gap
+    #[arg(long, default_value_t = 0.0)]
+    pub gap_probability: f64,
+    /// Duration of the gaps, in seconds
+    #[arg(long, default_value_t = 1800.0)]
+    pub gap_duration: f64,
+    /// Position of the port the vessels leave from and return to
+    #[arg(long, value_name = "X,Y", value_parser = parse_point, default_value = "0,0")]
+    pub port: Point,
+    /// Seed of the random number generator, a random seed is used otherwise
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+/// Parameters of the particle filter. Flags override the values of the run
+/// configuration file, which override the defaults.
+#[derive(Debug, Args)]
+pub struct FilterArgs {
+    /// Run configuration file (TOML, or JSON with a .json extension)
+    #[arg(long)]
+    pub config: Option<String>,
+    /// Decoder labelling the observations: particle-filter, viterbi or
+    /// forward-backward
+    #[arg(long)]
+    pub decoder: Option<Decoder>,
+    /// Number of particles
+    #[arg(long)]
+    pub particles: Option<u16>,
+    /// Standard deviation of the observation noise
+    #[arg(long)]
+    pub sigma: Option<f64>,
+    /// Resampling scheme of the particle filter: multinomial, systematic,
+    /// stratified or residual
+    #[arg(long)]
+    pub resampling: Option<ResamplingScheme>,
+    /// Resample only when the effective sample size falls below this fraction
+    /// of the number of particles, 1 resamples at every observation
+    #[arg(long)]
+    pub ess_threshold: Option<f64>,
+    /// Recovery when the particle weights collapse: reinitialize,
+    /// inflate-sigma or skip
+    #[arg(long)]
+    pub collapse_policy: Option<CollapsePolicy>,
+    /// Observations more than this time after the previous one start a gap
+    #[arg(long)]
+    pub max_time_gap: Option<f64>,
+    /// Handling of the gaps: split or reinitialize
+    #[arg(long)]
+    pub gap_policy: Option<GapPolicy>,
+    /// Normal speed distribution of sailing particles. The speed and heading
+    /// flags only apply to the default contexts, not to `contexts`
+    #[arg(long, value_name = "MEAN,STD_DEV", value_parser = parse_speed_distribution)]
+    pub sailing_speed: Option<SpeedDistribution>,
+    /// Normal speed distribution of fishing particles
+    #[arg(long, value_name = "MEAN,STD_DEV", value_parser = parse_speed_distribution)]
+    pub fishing_speed: Option<SpeedDistribution>,
+    /// Half width of the heading change of fishing particles
+    #[arg(long)]
+    pub fishing_heading_jitter: Option<f64>,
+    /// Maximum heading change of sailing particles
+    #[arg(long)]
+    pub sailing_heading_jitter: Option<f64>,
+    /// Penalize the weight of fishing particles near the shore with
+    /// `1 - (1 - EPSILON) * exp(-d / D0)`, `d` being the distance to the
+    /// shore. There is no shore penalty by default
+    #[arg(long, value_name = "EPSILON,D0", value_parser = parse_shore_penalty)]
+    pub fishing_shore_penalty: Option<ShorePenaltyConfig>,
+    /// CSV file of the known ports, with name, x and y columns
+    #[arg(long)]
+    pub ports: Option<String>,
+    /// Name of the port the vessels leave from, the port nearest to the first
+    /// observation by default
+    #[arg(long)]
+    pub home_port: Option<String>,
+    /// Name of the port the vessels return to, the port nearest to each
+    /// particle by default
+    #[arg(long)]
+    pub destination_port: Option<String>,
+    /// CSV file of the gear type of each vessel, with id and gear columns
+    #[arg(long)]
+    pub vessel_registry: Option<String>,
+    /// Gear profile of the vessels whose gear is not known from the registry
+    /// or the gear column: trawler, longliner, purse-seiner, gillnetter or a
+    /// profile of the configuration
+    #[arg(long)]
+    pub default_gear: Option<String>,
+    /// Markov graph file of the context transitions
+    #[arg(long)]
+    pub graph: Option<String>,
+    /// Seed of the random number generator, a random seed is used otherwise.
+    /// In batch and --by-vessel runs, each trajectory uses a seed derived
+    /// from this one and its relative path or vessel id
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Do not write the run configuration next to the result files
+    #[arg(long)]
+    pub no_echo_config: bool,
+    /// Write the trip phases, runs of observations in the same context, next
+    /// to the result files as `<result>.segments.<csv|json>`
+    #[arg(long, value_name = "csv|json")]
+    pub segments: Option<SegmentFormat>,
+}
+
+impl FilterArgs {
+    /// Builds the run configuration from the config file and the flags.
+    pub fn to_config(&self) -> Result<RunConfig, Error> {
+        let mut config = match &self.config {
+            Some(path) => RunConfig::from_file(path)?,
+            None => RunConfig::default(),
+        };
+
+        if let Some(decoder) = self.decoder {
+            config.decoder = decoder;
+        }
+        if let Some(particles) = self.particles {
+            config.nb_of_particles = particles;
+        }
+        if let Some(sigma) = self.sigma {
+            config.sigma = sigma;
+        }
+        if let Some(resampling) = self.resampling {
+            config.resampling = resampling;
+        }
+        if let Some(ess_threshold) = self.ess_threshold {
+            config.ess_threshold = ess_threshold;
+        }
+        if let Some(policy) = self.collapse_policy {
+            config.collapse.policy = policy;
+        }
+        if let Some(max_time_gap) = self.max_time_gap {
+            config.gap.max_time_gap = Some(max_time_gap);
+        }
+        if let Some(policy) = self.gap_policy {
+            config.gap.policy = policy;
+        }
+        if let Some(speed) = self.sailing_speed {
+            config.sailing_speed = speed;
+        }
+        if let Some(speed) = self.fishing_speed {
+            config.fishing_speed = speed;
+        }
+        if let Some(jitter) = self.fishing_heading_jitter {
+            config.heading.fishing_jitter = jitter;
+        }
+        if let Some(jitter) = self.sailing_heading_jitter {
+            config.heading.sailing_jitter = jitter;
+        }
+        if let Some(penalty) = self.fishing_shore_penalty {
+            config
+                .shore_penalty
+                .insert(ParticleContextType::fishing(), penalty);
+        }
+        if let Some(ports) = &self.ports {
+            config.ports.path = Some(ports.clone());
+        }
+        if let Some(home_port) = &self.home_port {
+            config.ports.home = Some(home_port.clone());
+        }
+        if let Some(destination_port) = &self.destination_port {
+            config.ports.destination = Some(destination_port.clone());
+        }
+        if let Some(vessel_registry) = &self.vessel_registry {
+            config.gear.registry = Some(vessel_registry.clone());
+        }
+        if let Some(default_gear) = &self.default_gear {
+            config.gear.default = Some(default_gear.clone());
+        }
+        if let Some(graph) = &self.graph {
+            config.graph_path = graph.clone();
+        }
+        if let Some(seed) = self.seed {
+            config.seed = Some(seed);
+        }
+        if self.no_echo_config {
+            config.output.echo_config = false;
+        }
+        if let Some(segments) = self.segments {
+            config.output.segments = Some(segments);
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_speed_distribution(s: &str) -> Result<SpeedDistribution, String> {
+    let (mean, std_dev) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected MEAN,STD_DEV, got {}", s))?;
+    let mean: f64 = mean
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid mean: {}", mean))?;
+    let std_dev: f64 = std_dev
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid standard deviation: {}", std_dev))?;
+
+    Ok(SpeedDistribution::Normal { mean, std_dev })
+}
+
+fn parse_shore_penalty(s: &str) -> Result<ShorePenaltyConfig, String> {
+    let (epsilon, d0) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected EPSILON,D0, got {}", s))?;
+    let epsilon: f64 = epsilon
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid epsilon: {}", epsilon))?;
+    let d0: f64 = d0
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid d0: {}", d0))?;
+
+    Ok(ShorePenaltyConfig {
+        epsilon,
+        d0: ShoreRange::Fixed(d0),
+    })
+}
+
+fn parse_point(s: &str) -> Result<Point, String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected X,Y, got {}", s))?;
+    let x: f64 = x.trim().parse().map_err(|_| format!("invalid x: {}", x))?;
+    let y: f64 = y.trim().parse().map_err(|_| format!("invalid y: {}", y))?;
+
+    Ok(Point { x, y })
+}
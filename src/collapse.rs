@@ -0,0 +1,88 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Detection of and recovery from a collapse of the particle weights, when no
+/// particle can explain an observation, e.g. after a GPS glitch or a long gap.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollapseConfig {
+    pub policy: CollapsePolicy,
+    /// The weights also collapse when the nearest particle is farther from
+    /// the observation than this number of `sigma`. With `inf`, they only
+    /// collapse when every particle has a zero likelihood, which the log
+    /// weights of a finite distance never have
+    pub max_distance_sigmas: f64,
+    /// Factor `sigma` is multiplied by at each try of the `inflate-sigma`
+    /// policy
+    pub sigma_inflation: f64,
+    /// Number of tries of the `inflate-sigma` policy before falling back to
+    /// reinitializing the particles
+    pub max_inflations: u32,
+}
+
+/// What to do with an observation the particles collapsed on
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollapsePolicy {
+    /// Move every particle to the observation, keeping its context, with
+    /// uniform weights
+    #[default]
+    Reinitialize,
+    /// Weight the particles again with a larger `sigma` for this observation
+    InflateSigma,
+    /// Ignore the observation: the particles are put back as they were before
+    /// the step, with their contexts and weights
+    Skip,
+}
+
+impl Default for CollapseConfig {
+    fn default() -> Self {
+        CollapseConfig {
+            policy: CollapsePolicy::default(),
+            max_distance_sigmas: 100.0,
+            sigma_inflation: 2.0,
+            max_inflations: 10,
+        }
+    }
+}
+
+impl CollapseConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.max_distance_sigmas.is_nan() || self.max_distance_sigmas <= 0.0 {
+            return Err(Error::Config(
+                "collapse.max_distance_sigmas must be positive".into(),
+            ));
+        }
+        if !(self.sigma_inflation.is_finite() && self.sigma_inflation > 1.0) {
+            return Err(Error::Config(
+                "collapse.sigma_inflation must be greater than 1".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CollapsePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reinitialize" => Ok(CollapsePolicy::Reinitialize),
+            "inflate-sigma" => Ok(CollapsePolicy::InflateSigma),
+            "skip" => Ok(CollapsePolicy::Skip),
+            _ => Err(format!("Invalid collapse policy: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for CollapsePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CollapsePolicy::Reinitialize => write!(f, "reinitialize"),
+            CollapsePolicy::InflateSigma => write!(f, "inflate-sigma"),
+            CollapsePolicy::Skip => write!(f, "skip"),
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use crate::error::Error;
+use crate::gear::builtin_profile;
+use crate::markov_graph::{read_graph_from_file, MarkovGraph};
+use crate::observation::AisRecord;
+use crate::particle::ParticleContextType;
+use crate::port::PortRegistry;
+use crate::random_generator::MAX_SEED;
+use crate::segment::SegmentFormat;
+
+// Sections and parameters of a run configuration
+pub use crate::collapse::{CollapseConfig, CollapsePolicy};
+pub use crate::context::{ContextConfig, ContextSet, HeadingModel};
+pub use crate::distribution::{DistributionFamily, SpeedDistribution};
+pub use crate::gap::{GapConfig, GapPolicy};
+pub use crate::gear::{GearConfig, GearProfile, VesselRegistry, BUILTIN_PROFILES};
+pub use crate::geometry::Point;
+pub use crate::hmm::HmmConfig;
+pub use crate::port::PortsConfig;
+pub use crate::resampling::ResamplingScheme;
+pub use crate::shore::{ShorePenaltyConfig, ShoreRange};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// All tuning parameters of a context matching run. A run configuration can
+/// be loaded from a TOML or JSON file; every field falls back to the values
+/// that used to be hardcoded in `main.rs` when it is not given.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunConfig {
+    /// Algorithm labelling the observations with contexts
+    pub decoder: Decoder,
+    pub nb_of_particles: u16,
+    pub sigma: f64,
+    /// Resampling scheme of the particle filter
+    pub resampling: ResamplingScheme,
+    /// The particles are resampled when their effective sample size falls
+    /// below this fraction of `nb_of_particles`, at every step with 1
+    pub ess_threshold: f64,
+    /// Recovery of the particle filter when no particle explains an
+    /// observation
+    pub collapse: CollapseConfig,
+    /// Handling of the outages of the tracks, none by default
+    pub gap: GapConfig,
+    /// Context states of the vessels with their motion models. Without
+    /// them, the GoFishing, Fishing and GoToPort contexts are defined from
+    /// `sailing_speed`, `fishing_speed` and `heading`
+    pub contexts: Vec<ContextConfig>,
+    /// Selection of the gear profile of each vessel
+    pub gear: GearConfig,
+    /// Gear profiles, in addition to the built-in `trawler`, `longliner`,
+    /// `purse-seiner` and `gillnetter` profiles, which they override
+    pub profiles: BTreeMap<String, GearProfile>,
+    pub sailing_speed: SpeedDistribution,
+    pub fishing_speed: SpeedDistribution,
+    pub heading: HeadingConfig,
+    /// Penalization of the weight of the particles of some contexts near the
+    /// shore, none by default
+    pub shore_penalty: BTreeMap<ParticleContextType, ShorePenaltyConfig>,
+    pub ports: PortsConfig,
+    pub graph_path: String,
+    /// Emission model of the `viterbi` and `forward-backward` decoders
+    pub hmm: HmmConfig,
+    pub history: HistoryConfig,
+    pub output: OutputConfig,
+    /// Seed of the random number generator. Runs on the same input with the
+    /// same seed give identical results.
+    pub seed: Option<u64>,
+}
+
+/// Algorithm labelling the observations with contexts. The particle filter
+/// simulates the motion of the vessel, while the Viterbi decoder finds the
+/// most likely sequence of contexts of a hidden Markov model whose
+/// transitions are the Markov graph and whose emissions are computed from the
+/// speed, turn and distance to the shore of each observation. The
+/// forward-backward decoder labels each observation with its most probable
+/// context under the same model.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Decoder {
+    #[default]
+    ParticleFilter,
+    Viterbi,
+    ForwardBackward,
+}
+
+/// Amount of random change applied to the heading of a particle at each step.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeadingConfig {
+    /// Half width of the uniform heading change of fishing particles, in
+    /// degrees
+    pub fishing_jitter: f64,
+    /// Maximum heading change of sailing particles steering to/from the
+    /// coast, in degrees
+    pub sailing_jitter: f64,
+}
+
+/// Recording of the particle memories into a history file. Only the first
+/// `head` steps, the `middle` steps before the middle of the trajectory and
+/// the last `tail` steps are recorded to keep the file at a reasonable size.
+/// Only the particle filter has particle memories: a `path` is an invalid
+/// configuration with the other decoders.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub path: Option<String>,
+    pub head: usize,
+    pub middle: usize,
+    pub tail: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Write the configuration used for the run next to the result file
+    pub echo_config: bool,
+    /// Write the trip phases of the vessels next to the result file in this
+    /// format, see `Segment`
+    pub segments: Option<SegmentFormat>,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        RunConfig {
+            decoder: Decoder::default(),
+            nb_of_particles: 100,
+            sigma: 5.0,
+            resampling: ResamplingScheme::default(),
+            ess_threshold: 1.0,
+            collapse: CollapseConfig::default(),
+            gap: GapConfig::default(),
+            contexts: Vec::new(),
+            gear: GearConfig::default(),
+            profiles: BTreeMap::new(),
+            sailing_speed: SpeedDistribution::Normal {
+                mean: 3.31,
+                std_dev: 1.19,
+            },
+            fishing_speed: SpeedDistribution::Normal {
+                mean: 1.36,
+                std_dev: 0.89,
+            },
+            heading: HeadingConfig::default(),
+            shore_penalty: BTreeMap::new(),
+            ports: PortsConfig::default(),
+            graph_path: String::from("src/graph.txt"),
+            hmm: HmmConfig::default(),
+            history: HistoryConfig::default(),
+            output: OutputConfig::default(),
+            seed: None,
+        }
+    }
+}
+
+impl Default for HeadingConfig {
+    fn default() -> Self {
+        HeadingConfig {
+            // 0.4 radians
+            fishing_jitter: 22.9,
+            sailing_jitter: 22.0,
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            path: None,
+            head: 50,
+            middle: 100,
+            tail: 50,
+        }
+    }
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            echo_config: true,
+            segments: None,
+        }
+    }
+}
+
+impl RunConfig {
+    /// Reads a run configuration from a file. Files with a `.json` extension
+    /// are parsed as JSON, everything else as TOML.
+    pub fn from_file(filename: &str) -> Result<RunConfig, Error> {
+        let content = fs::read_to_string(filename).map_err(|e| Error::io(filename, e))?;
+
+        let config: RunConfig = if has_extension(filename, "json") {
+            serde_json::from_str(&content)
+                .map_err(|e| Error::Config(format!("invalid config file {}: {}", filename, e)))?
+        } else {
+            toml::from_str(&content)
+                .map_err(|e| Error::Config(format!("invalid config file {}: {}", filename, e)))?
+        };
+
+        Ok(config)
+    }
+
+    /// Checks that the parameters make sense before running anything: the
+    /// parameters themselves, see `check_parameters`, the files they refer
+    /// to, i.e. the Markov graph, the port file and the vessel registry, and
+    /// every gear profile selected by the configuration.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.check_parameters()?;
+        self.check_files()?;
+        for name in self.profiles.keys().chain(&self.gear.default) {
+            self.with_profile(name)?
+                .check_files()
+                .map_err(|e| in_profile(name, e))?;
+        }
+        Ok(())
+    }
+
+    /// Checks the parameters of the configuration without reading any file.
+    /// Decoding only runs these checks, the configuration being validated
+    /// when it is loaded.
+    pub fn check_parameters(&self) -> Result<(), Error> {
+        if self.nb_of_particles == 0 {
+            return Err(Error::Config(
+                "nb_of_particles must be greater than 0".into(),
+            ));
+        }
+        if !(self.sigma.is_finite() && self.sigma > 0.0) {
+            return Err(Error::Config(format!(
+                "sigma must be positive, got {}",
+                self.sigma
+            )));
+        }
+        if !(0.0..=1.0).contains(&self.ess_threshold) {
+            return Err(Error::Config(format!(
+                "ess_threshold must be between 0 and 1, got {}",
+                self.ess_threshold
+            )));
+        }
+        self.collapse.validate()?;
+        self.gap.validate()?;
+        let contexts = self.context_set();
+        contexts.validate()?;
+        self.sailing_speed.validate("sailing_speed")?;
+        self.fishing_speed.validate("fishing_speed")?;
+        if !(self.heading.fishing_jitter.is_finite() && self.heading.fishing_jitter >= 0.0) {
+            return Err(Error::Config(
+                "heading.fishing_jitter must be a non-negative number".into(),
+            ));
+        }
+        if !(self.heading.sailing_jitter.is_finite() && self.heading.sailing_jitter >= 0.0) {
+            return Err(Error::Config(
+                "heading.sailing_jitter must be a non-negative number".into(),
+            ));
+        }
+        for (context, penalty) in &self.shore_penalty {
+            if contexts.get(context).is_none() {
+                return Err(Error::Config(format!(
+                    "shore_penalty.{} is not a context",
+                    context
+                )));
+            }
+            penalty.validate(&format!("shore_penalty.{}", context))?;
+        }
+        // Only the particle filter has particle memories to record
+        if self.history.path.is_some() && self.decoder != Decoder::ParticleFilter {
+            return Err(Error::Config(format!(
+                "history.path requires the particle-filter decoder, not {}",
+                self.decoder
+            )));
+        }
+        if self.seed.is_some_and(|seed| seed > MAX_SEED) {
+            return Err(Error::Config(format!("seed must be at most {}", MAX_SEED)));
+        }
+        Ok(())
+    }
+
+    /// Checks the files of the configuration, not those of its gear profiles.
+    fn check_files(&self) -> Result<(), Error> {
+        self.ports.validate()?;
+        if let Some(registry) = &self.gear.registry {
+            if !Path::new(registry).is_file() {
+                return Err(Error::Config(format!(
+                    "vessel registry {} does not exist",
+                    registry
+                )));
+            }
+        }
+        if !Path::new(&self.graph_path).is_file() {
+            return Err(Error::Config(format!(
+                "graph file {} does not exist",
+                self.graph_path
+            )));
+        }
+        let markov_graph: MarkovGraph<ParticleContextType> =
+            read_graph_from_file(&self.graph_path)?;
+        self.check_graph(&markov_graph)
+    }
+
+    /// Checks that the Markov graph of `graph_path` is a valid graph over
+    /// the contexts of the configuration, whose initial context is a node of
+    /// the graph.
+    pub fn check_graph(
+        &self,
+        markov_graph: &MarkovGraph<ParticleContextType>,
+    ) -> Result<(), Error> {
+        let contexts = self.context_set();
+        markov_graph
+            .validate()
+            .map_err(|e| Error::Config(format!("invalid graph file {}: {}", self.graph_path, e)))?;
+        for node in markov_graph.get_all_nodes() {
+            if contexts.get(&node).is_none() {
+                return Err(Error::Config(format!(
+                    "context {} of the graph file {} is not defined in contexts",
+                    node, self.graph_path
+                )));
+            }
+        }
+        if !markov_graph.get_all_nodes().contains(&contexts.initial()) {
+            return Err(Error::Config(format!(
+                "initial context {} is not a node of the graph file {}",
+                contexts.initial(),
+                self.graph_path
+            )));
+        }
+        Ok(())
+    }
+
+    /// Gear profile of a name, from `profiles` or the built-in ones.
+    pub fn profile(&self, name: &str) -> Option<GearProfile> {
+        self.profiles
+            .get(name)
+            .cloned()
+            .or_else(|| builtin_profile(name))
+    }
+
+    /// Configuration of the vessels of a gear profile: this configuration
+    /// with the contexts, graph and shore penalties of the profile. The
+    /// parameters of the result are checked, see `check_parameters`; its
+    /// files are checked by `validate`.
+    pub fn with_profile(&self, name: &str) -> Result<RunConfig, Error> {
+        let profile = self
+            .profile(name)
+            .ok_or_else(|| Error::Config(format!("unknown gear profile {}", name)))?;
+
+        let mut config = self.clone();
+        if !profile.contexts.is_empty() {
+            config.contexts = profile.contexts;
+        }
+        if let Some(graph_path) = profile.graph_path {
+            config.graph_path = graph_path;
+        }
+        if let Some(shore_penalty) = profile.shore_penalty {
+            config.shore_penalty = shore_penalty;
+        }
+        // The profile is resolved
+        config.gear = GearConfig::default();
+        config.profiles = BTreeMap::new();
+
+        config.check_parameters().map_err(|e| in_profile(name, e))?;
+        Ok(config)
+    }
+
+    /// Gear of a vessel and its configuration, with its gear profile if it
+    /// has one.
+    pub fn for_vessel(
+        &self,
+        id: &str,
+        records: &[AisRecord],
+        registry: &VesselRegistry,
+    ) -> Result<(Option<String>, RunConfig), Error> {
+        let gear = self.gear.gear_of(id, records, registry);
+        let config = match &gear {
+            Some(gear) => self.with_profile(gear)?,
+            None => self.clone(),
+        };
+        Ok((gear, config))
+    }
+
+    /// Context states of the run: `contexts`, or the default GoFishing,
+    /// Fishing and GoToPort contexts.
+    pub fn context_set(&self) -> ContextSet {
+        if !self.contexts.is_empty() {
+            return ContextSet::new(self.contexts.clone());
+        }
+
+        ContextSet::new(vec![
+            ContextConfig {
+                name: ParticleContextType::go_fishing(),
+                labels: vec![String::from("01-sailing")],
+                speed: self.sailing_speed,
+                heading: HeadingModel::AwayFromPort {
+                    jitter: self.heading.sailing_jitter,
+                },
+            },
+            ContextConfig {
+                name: ParticleContextType::fishing(),
+                labels: vec![String::from("02-fishing")],
+                speed: self.fishing_speed,
+                heading: HeadingModel::RandomWalk {
+                    jitter: self.heading.fishing_jitter,
+                },
+            },
+            ContextConfig {
+                name: ParticleContextType::go_to_port(),
+                labels: vec![String::from("03-sailing")],
+                speed: self.sailing_speed,
+                heading: HeadingModel::TowardPort {
+                    jitter: self.heading.sailing_jitter,
+                },
+            },
+        ])
+    }
+
+    /// Serializes the configuration as TOML so it can be stored with results.
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string_pretty(self)
+    }
+
+    /// Writes the configuration to a file, as JSON if its extension is
+    /// `.json` and as TOML otherwise.
+    pub fn to_file(&self, filename: &str) -> Result<(), Error> {
+        let content = if has_extension(filename, "json") {
+            serde_json::to_string_pretty(self).map_err(|e| unserializable(&e))?
+        } else {
+            self.to_toml().map_err(|e| unserializable(&e))?
+        };
+        fs::write(filename, content).map_err(|e| Error::io(filename, e))
+    }
+
+    /// Writes the configuration next to a result file as `<result>.config.toml`.
+    pub fn echo_to(&self, result_path: &str) -> Result<String, Error> {
+        let path = format!("{}.config.toml", result_path);
+        let content = self.to_toml().map_err(|e| unserializable(&e))?;
+        fs::write(&path, content).map_err(|e| Error::io(&path, e))?;
+        Ok(path)
+    }
+}
+
+/// Files of a run configuration, read once to decode many trajectories with
+/// `decode_with_files`: the Markov graphs of the run and of its gear
+/// profiles, and the port registry.
+#[derive(Debug, Clone, Default)]
+pub struct RunFiles {
+    markov_graphs: BTreeMap<String, MarkovGraph<ParticleContextType>>,
+    ports: Option<PortRegistry>,
+}
+
+impl RunFiles {
+    /// Reads the files of a configuration and of its gear profiles.
+    pub fn read(config: &RunConfig) -> Result<RunFiles, Error> {
+        let mut markov_graphs = BTreeMap::new();
+        let graph_paths = config
+            .profiles
+            .values()
+            .filter_map(|profile| profile.graph_path.as_ref())
+            .chain([&config.graph_path]);
+        for path in graph_paths {
+            if !markov_graphs.contains_key(path) {
+                markov_graphs.insert(path.clone(), read_graph_from_file(path)?);
+            }
+        }
+
+        Ok(RunFiles {
+            markov_graphs,
+            ports: config.ports.read_registry()?,
+        })
+    }
+
+    /// Markov graph of a file, read again if it is not one of the files of
+    /// the configuration.
+    pub fn markov_graph(
+        &self,
+        path: &str,
+    ) -> Result<Cow<'_, MarkovGraph<ParticleContextType>>, Error> {
+        match self.markov_graphs.get(path) {
+            Some(markov_graph) => Ok(Cow::Borrowed(markov_graph)),
+            None => Ok(Cow::Owned(read_graph_from_file(path)?)),
+        }
+    }
+
+    /// Port registry of the configuration, `None` without a port file.
+    pub fn ports(&self) -> Option<&PortRegistry> {
+        self.ports.as_ref()
+    }
+}
+
+impl FromStr for Decoder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "particle-filter" => Ok(Decoder::ParticleFilter),
+            "viterbi" => Ok(Decoder::Viterbi),
+            "forward-backward" => Ok(Decoder::ForwardBackward),
+            _ => Err(format!("Invalid decoder: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Decoder::ParticleFilter => write!(f, "particle-filter"),
+            Decoder::Viterbi => write!(f, "viterbi"),
+            Decoder::ForwardBackward => write!(f, "forward-backward"),
+        }
+    }
+}
+
+fn unserializable(e: &dyn fmt::Display) -> Error {
+    Error::Config(format!("cannot serialize the configuration: {}", e))
+}
+
+/// Error of a gear profile, with its name.
+fn in_profile(name: &str, e: Error) -> Error {
+    match e {
+        Error::Config(message) => Error::Config(format!("gear profile {}: {}", name, message)),
+        e => e,
+    }
+}
+
+fn has_extension(filename: &str, extension: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
@@ -0,0 +1,181 @@
+use crate::distribution::SpeedDistribution;
+use crate::error::Error;
+use crate::particle::ParticleContextType;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// A context state of the vessels and its motion model
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ContextConfig {
+    pub name: ParticleContextType,
+    /// Values of the `label` column of the AIS files that mean this context
+    #[serde(default)]
+    pub labels: Vec<String>,
+    pub speed: SpeedDistribution,
+    pub heading: HeadingModel,
+}
+
+/// How the particles of a context change their heading at each step
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "model", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum HeadingModel {
+    /// Uniform heading change in `[-jitter, jitter]`
+    RandomWalk { jitter: f64 },
+    /// Uniform turn of at most `jitter` away from the home port
+    AwayFromPort { jitter: f64 },
+    /// Uniform turn of at most `jitter` toward the destination port
+    TowardPort { jitter: f64 },
+    /// Turn of `rate` in the direction of the previous turn of the particle,
+    /// plus a uniform change in `[-jitter, jitter]`, e.g. a purse seiner
+    /// setting its net around a school
+    Circling { rate: f64, jitter: f64 },
+}
+
+/// Ordered set of the context states of a run. The first context is the
+/// context of the vessels at the first observation, and of the observations
+/// whose label is unknown.
+#[derive(Debug, Clone)]
+pub struct ContextSet {
+    contexts: Vec<ContextConfig>,
+}
+
+impl HeadingModel {
+    pub fn jitter(&self) -> f64 {
+        match *self {
+            HeadingModel::RandomWalk { jitter }
+            | HeadingModel::AwayFromPort { jitter }
+            | HeadingModel::TowardPort { jitter }
+            | HeadingModel::Circling { jitter, .. } => jitter,
+        }
+    }
+
+    /// Root mean square of the heading change of a step. Uniform changes in
+    /// `[-jitter, jitter]` or turns in `[0, jitter]` have a mean square of
+    /// `jitter^2 / 3`.
+    pub fn turn_rms(&self) -> f64 {
+        match *self {
+            HeadingModel::Circling { rate, jitter } => (rate.powi(2) + jitter.powi(2) / 3.0).sqrt(),
+            _ => self.jitter() / 3.0f64.sqrt(),
+        }
+    }
+
+    /// The same model with another jitter.
+    pub fn with_jitter(self, jitter: f64) -> HeadingModel {
+        match self {
+            HeadingModel::RandomWalk { .. } => HeadingModel::RandomWalk { jitter },
+            HeadingModel::AwayFromPort { .. } => HeadingModel::AwayFromPort { jitter },
+            HeadingModel::TowardPort { .. } => HeadingModel::TowardPort { jitter },
+            HeadingModel::Circling { rate, .. } => HeadingModel::Circling { rate, jitter },
+        }
+    }
+}
+
+impl ContextSet {
+    pub fn new(contexts: Vec<ContextConfig>) -> ContextSet {
+        ContextSet { contexts }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ContextConfig> {
+        self.contexts.iter()
+    }
+
+    pub fn names(&self) -> Vec<ParticleContextType> {
+        self.contexts
+            .iter()
+            .map(|context| context.name.clone())
+            .collect()
+    }
+
+    pub fn initial(&self) -> ParticleContextType {
+        self.contexts[0].name.clone()
+    }
+
+    pub fn get(&self, name: &ParticleContextType) -> Option<&ContextConfig> {
+        self.contexts.iter().find(|context| context.name == *name)
+    }
+
+    /// Context of a name, an error if it is not in the set, e.g. a node of a
+    /// Markov graph that is not defined in the contexts.
+    pub fn lookup(&self, name: &ParticleContextType) -> Result<&ContextConfig, Error> {
+        self.get(name)
+            .ok_or_else(|| Error::Config(format!("context {} is not defined in contexts", name)))
+    }
+
+    /// Contexts of the set among `nodes`, e.g. the nodes of a Markov graph,
+    /// in the order of the set.
+    pub fn order(&self, nodes: &[ParticleContextType]) -> Vec<ParticleContextType> {
+        self.contexts
+            .iter()
+            .map(|context| context.name.clone())
+            .filter(|name| nodes.contains(name))
+            .collect()
+    }
+
+    /// Context of a label of the AIS files. Labels may also be the name of a
+    /// context.
+    pub fn context_of(&self, label: &str) -> ParticleContextType {
+        self.contexts
+            .iter()
+            .find(|context| {
+                context.labels.iter().any(|l| l == label) || context.name.name() == label
+            })
+            .map_or_else(|| self.initial(), |context| context.name.clone())
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.contexts.is_empty() {
+            return Err(Error::Config("contexts must not be empty".into()));
+        }
+
+        let mut names = BTreeSet::new();
+        let mut labels = BTreeSet::new();
+        for context in &self.contexts {
+            let name = format!("contexts.{}", context.name);
+            if !names.insert(&context.name) {
+                return Err(Error::Config(format!("{} is defined twice", name)));
+            }
+            for label in &context.labels {
+                if !labels.insert(label) {
+                    return Err(Error::Config(format!(
+                        "label {} is mapped to several contexts",
+                        label
+                    )));
+                }
+            }
+            context.speed.validate(&format!("{}.speed", name))?;
+            let jitter = context.heading.jitter();
+            if !(jitter.is_finite() && jitter >= 0.0) {
+                return Err(Error::Config(format!(
+                    "{}.heading.jitter must be a non-negative number",
+                    name
+                )));
+            }
+            if let HeadingModel::Circling { rate, .. } = context.heading {
+                if !rate.is_finite() {
+                    return Err(Error::Config(format!(
+                        "{}.heading.rate must be a number",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for HeadingModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeadingModel::RandomWalk { jitter } => write!(f, "random-walk(jitter={:.3})", jitter),
+            HeadingModel::AwayFromPort { jitter } => {
+                write!(f, "away-from-port(jitter={:.3})", jitter)
+            }
+            HeadingModel::TowardPort { jitter } => write!(f, "toward-port(jitter={:.3})", jitter),
+            HeadingModel::Circling { rate, jitter } => {
+                write!(f, "circling(rate={:.3}, jitter={:.3})", rate, jitter)
+            }
+        }
+    }
+}
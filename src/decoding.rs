@@ -0,0 +1,576 @@
+use crate::config::{Decoder, RunConfig, RunFiles};
+use crate::error::Error;
+use crate::fishing_context::FishingContext;
+use crate::gap::{nb_of_steps, GapPolicy};
+use crate::hmm::{self, Hmm};
+use crate::markov_graph::MarkovGraph;
+use crate::observation::{AisRecord, ContextPosteriors, Observation, StepEvent};
+use crate::particle::ParticleContextType;
+use crate::random_generator::derive_seed;
+use crate::segment::{self, Segment};
+use std::path::Path;
+
+/// Contexts decoded for one trajectory
+#[derive(Debug, Clone, Default)]
+pub struct Decoding {
+    /// The observations labelled with their decoded context
+    pub states: Vec<Observation>,
+    pub posteriors: ContextPosteriors,
+    /// Event of the decoder at each observation, e.g. the recovery from a
+    /// collapse of the particle weights
+    pub events: Vec<StepEvent>,
+}
+
+impl Decoding {
+    /// Appends the decoding of the next segment of the trajectory.
+    fn append(&mut self, mut other: Decoding) {
+        self.states.append(&mut other.states);
+        self.posteriors.contexts = other.posteriors.contexts;
+        self.posteriors.probs.append(&mut other.posteriors.probs);
+        self.events.append(&mut other.events);
+    }
+
+    /// Trip phases of the decoded trajectory of a vessel.
+    pub fn segments(&self, id: &str, records: &[AisRecord]) -> Vec<Segment> {
+        let timestamps: Vec<String> = records.iter().map(|record| record.t.clone()).collect();
+        segment::segments(
+            id,
+            &self.states,
+            &self.posteriors,
+            &self.events,
+            &timestamps,
+        )
+    }
+}
+
+/// Labels the observations with the decoder of the configuration. With the
+/// `split` gap policy, the segments between the gaps are decoded separately,
+/// each one starting from the contexts at the end of the previous one after
+/// the transitions of the graph over the gap. Reads the files of the configuration, see
+/// `decode_with_files` to decode many trajectories.
+pub fn decode(observations: &[Observation], config: &RunConfig) -> Result<Decoding, Error> {
+    decode_with_files(observations, config, &RunFiles::read(config)?)
+}
+
+/// Labels the observations like `decode`, with the files of the
+/// configuration already read. The configuration is expected to be validated
+/// when it is loaded, see `RunConfig::validate`: only its parameters and its
+/// Markov graph are checked, and it fails with `Error::Config` if they are
+/// not valid.
+pub fn decode_with_files(
+    observations: &[Observation],
+    config: &RunConfig,
+    files: &RunFiles,
+) -> Result<Decoding, Error> {
+    config.check_parameters()?;
+    let markov_graph = files.markov_graph(&config.graph_path)?;
+    config.check_graph(&markov_graph)?;
+    if observations.is_empty() {
+        return Ok(Decoding::default());
+    }
+    if config.gap.policy == GapPolicy::Split {
+        let segments = config.gap.segments(observations);
+        let time_step = config.gap.resolve_time_step(observations);
+        let mut decoding = Decoding::default();
+        let mut prior: Option<Vec<(ParticleContextType, f64)>> = None;
+        for (k, segment) in segments.iter().enumerate() {
+            let mut segment_config = config.clone();
+            if segments.len() > 1 {
+                let key = format!("segment{}", k);
+                segment_config.history.path = config
+                    .history
+                    .path
+                    .as_ref()
+                    .map(|path| vessel_file_path(path, &key));
+                segment_config.seed = config.seed.map(|seed| derive_seed(seed, &key));
+            }
+
+            let mut segment_decoding = run_decoder(
+                &observations[segment.clone()],
+                &segment_config,
+                &markov_graph,
+                files,
+                prior.as_deref(),
+            )?;
+            if k > 0 {
+                segment_decoding.events[0] = StepEvent::Gap(GapPolicy::Split);
+            }
+            // The next segment starts from the contexts of the end of this
+            // one, after the transitions of the gap
+            prior = segments.get(k + 1).and_then(|next| {
+                prior_after_gap(
+                    &segment_decoding.posteriors,
+                    &markov_graph,
+                    nb_of_steps(observations[next.start].time, time_step),
+                )
+            });
+            decoding.append(segment_decoding);
+        }
+        Ok(decoding)
+    } else {
+        run_decoder(observations, config, &markov_graph, files, None)
+    }
+}
+
+/// Probability of each context after a gap of `nb_of_steps` transitions of
+/// the graph, from their probability at the last observation before the gap.
+fn prior_after_gap(
+    posteriors: &ContextPosteriors,
+    markov_graph: &MarkovGraph<ParticleContextType>,
+    nb_of_steps: u32,
+) -> Option<Vec<(ParticleContextType, f64)>> {
+    let last = posteriors.probs.last()?;
+    let graph = markov_graph.power(nb_of_steps);
+
+    Some(
+        posteriors
+            .contexts
+            .iter()
+            .map(|dest| {
+                let prob = posteriors
+                    .contexts
+                    .iter()
+                    .zip(last)
+                    .map(|(src, prob)| prob * graph.transition_prob(src, dest))
+                    .sum();
+                (dest.clone(), prob)
+            })
+            .collect(),
+    )
+}
+
+/// Runs the decoder of the configuration, starting from `prior`, see
+/// `Hmm::new`.
+fn run_decoder(
+    observations: &[Observation],
+    config: &RunConfig,
+    markov_graph: &MarkovGraph<ParticleContextType>,
+    files: &RunFiles,
+    prior: Option<&[(ParticleContextType, f64)]>,
+) -> Result<Decoding, Error> {
+    let decoding = match config.decoder {
+        Decoder::ParticleFilter => {
+            let mut ctx =
+                FishingContext::new(observations, config, markov_graph, files.ports(), prior)?;
+            let (states, posteriors) = ctx.particle_filter()?;
+            Decoding {
+                states,
+                posteriors,
+                events: ctx.events().to_vec(),
+            }
+        }
+        Decoder::Viterbi => {
+            let hmm = Hmm::new(observations, config, markov_graph, prior)?;
+            let path = hmm.viterbi(observations)?;
+            Decoding {
+                states: hmm::label_observations(observations, &path),
+                posteriors: hmm.forward_backward(observations)?,
+                events: hmm.events(observations),
+            }
+        }
+        Decoder::ForwardBackward => {
+            let hmm = Hmm::new(observations, config, markov_graph, prior)?;
+            let posteriors = hmm.forward_backward(observations)?;
+            let path = hmm::posterior_path(&posteriors);
+            Decoding {
+                states: hmm::label_observations(observations, &path),
+                posteriors,
+                events: hmm.events(observations),
+            }
+        }
+    };
+    Ok(decoding)
+}
+
+/// Adds the vessel id to a file path, e.g. `history.csv` becomes `history_<id>.csv`.
+pub fn vessel_file_path(path: &str, id: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, id, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, id),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Column names of the context probabilities, e.g. `p_Fishing`.
+fn posterior_headers(contexts: &[ParticleContextType]) -> Vec<String> {
+    contexts
+        .iter()
+        .map(|context| format!("p_{}", context))
+        .collect()
+}
+
+/// Writes the result file of a trajectory: its decoded states, the
+/// probability of each context and the events of the decoder.
+pub fn write_results(filename: &str, decoding: &Decoding) -> Result<(), Error> {
+    write_results_csv(filename, decoding).map_err(|e| Error::csv(filename, e))
+}
+
+fn write_results_csv(filename: &str, decoding: &Decoding) -> Result<(), csv::Error> {
+    let mut wtr = csv::Writer::from_path(filename)?;
+
+    let mut headers: Vec<String> = ["x", "y", "time", "heading", "speed", "context"]
+        .map(String::from)
+        .to_vec();
+    headers.extend(posterior_headers(&decoding.posteriors.contexts));
+    headers.push(String::from("event"));
+    wtr.write_record(&headers)?;
+
+    for ((state, probs), event) in decoding
+        .states
+        .iter()
+        .zip(&decoding.posteriors.probs)
+        .zip(&decoding.events)
+    {
+        wtr.serialize((
+            state.pos.x,
+            state.pos.y,
+            state.time,
+            state.heading,
+            state.speed,
+            &state.context,
+            probs,
+            event.to_string(),
+        ))?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Writes the result file of several vessels, with an `id` column.
+pub fn write_vessel_results(filename: &str, vessels: &[(String, Decoding)]) -> Result<(), Error> {
+    write_vessel_results_csv(filename, vessels).map_err(|e| Error::csv(filename, e))
+}
+
+fn write_vessel_results_csv(
+    filename: &str,
+    vessels: &[(String, Decoding)],
+) -> Result<(), csv::Error> {
+    let mut wtr = csv::Writer::from_path(filename)?;
+
+    let mut headers: Vec<String> = ["id", "x", "y", "time", "heading", "speed", "context"]
+        .map(String::from)
+        .to_vec();
+    // Vessels of different gear types may have different contexts: the file
+    // has a column for each context of any vessel
+    let mut contexts: Vec<ParticleContextType> = Vec::new();
+    for (_, decoding) in vessels {
+        for context in &decoding.posteriors.contexts {
+            if !contexts.contains(context) {
+                contexts.push(context.clone());
+            }
+        }
+    }
+    headers.extend(posterior_headers(&contexts));
+    headers.push(String::from("event"));
+    wtr.write_record(&headers)?;
+
+    for (id, decoding) in vessels {
+        let columns: Vec<Option<usize>> = contexts
+            .iter()
+            .map(|context| {
+                decoding
+                    .posteriors
+                    .contexts
+                    .iter()
+                    .position(|c| c == context)
+            })
+            .collect();
+
+        for ((state, probs), event) in decoding
+            .states
+            .iter()
+            .zip(&decoding.posteriors.probs)
+            .zip(&decoding.events)
+        {
+            let probs: Vec<f64> = columns
+                .iter()
+                .map(|column| column.map_or(0.0, |j| probs[j]))
+                .collect();
+            wtr.serialize((
+                id,
+                state.pos.x,
+                state.pos.y,
+                state.time,
+                state.heading,
+                state.speed,
+                &state.context,
+                probs,
+                event.to_string(),
+            ))?;
+        }
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gap::GapConfig;
+    use crate::markov_graph::read_graph_from_file;
+    use crate::shore::{ShorePenaltyConfig, ShoreRange};
+    use crate::simulate::{SimulationConfig, Simulator};
+
+    /// Simulated trajectories of the default configuration, with the
+    /// contexts they were simulated in
+    fn simulated_observations(config: &RunConfig) -> Vec<Vec<Observation>> {
+        let simulation = SimulationConfig {
+            nb_of_trajectories: 2,
+            seed: 5,
+            ..SimulationConfig::default()
+        };
+        let contexts = config.context_set();
+        let graph = read_graph_from_file(&config.graph_path).unwrap();
+        let simulator = Simulator::new(simulation, contexts.clone(), graph);
+
+        simulator
+            .ids()
+            .iter()
+            .map(|id| Observation::from_records(&simulator.trajectory(id).unwrap(), &contexts))
+            .collect()
+    }
+
+    fn contexts(decoding: &Decoding) -> Vec<ParticleContextType> {
+        decoding
+            .states
+            .iter()
+            .map(|state| state.context.clone())
+            .collect()
+    }
+
+    #[test]
+    fn decoding_with_a_seed_is_reproducible() {
+        let config = RunConfig {
+            seed: Some(7),
+            ..RunConfig::default()
+        };
+
+        for observations in simulated_observations(&config) {
+            let first = decode(&observations, &config).unwrap();
+            let second = decode(&observations, &config).unwrap();
+            assert_eq!(contexts(&first), contexts(&second));
+            assert_eq!(first.posteriors.probs, second.posteriors.probs);
+        }
+    }
+
+    #[test]
+    fn decoders_recover_most_of_the_simulated_contexts() {
+        for decoder in [
+            Decoder::ParticleFilter,
+            Decoder::Viterbi,
+            Decoder::ForwardBackward,
+        ] {
+            let config = RunConfig {
+                decoder,
+                seed: Some(7),
+                ..RunConfig::default()
+            };
+
+            let (mut correct, mut total) = (0, 0);
+            for observations in simulated_observations(&config) {
+                let decoding = decode(&observations, &config).unwrap();
+                correct += contexts(&decoding)
+                    .iter()
+                    .zip(&observations)
+                    .filter(|(context, observation)| **context == observation.context)
+                    .count();
+                total += observations.len();
+            }
+
+            let success_rate = correct as f64 / total as f64;
+            assert!(
+                success_rate > 0.6,
+                "{}: success rate {}",
+                decoder,
+                success_rate
+            );
+        }
+    }
+
+    #[test]
+    fn a_segment_after_a_split_gap_starts_in_the_contexts_before_the_gap() {
+        let config = RunConfig {
+            seed: Some(11),
+            ..RunConfig::default()
+        };
+        let mut observations = simulated_observations(&config).remove(0);
+
+        // A gap in the middle of a fishing run
+        let fishing = ParticleContextType::fishing();
+        let gap = (5..observations.len())
+            .find(|&i| observations[i - 5..=i].iter().all(|o| o.context == fishing))
+            .unwrap();
+        let max_time = observations.iter().map(|o| o.time).fold(0.0, f64::max);
+        observations[gap].time = 3.0 * max_time;
+        let config = RunConfig {
+            gap: GapConfig {
+                max_time_gap: Some(2.0 * max_time),
+                policy: GapPolicy::Split,
+                time_step: None,
+            },
+            ..config
+        };
+
+        for decoder in [
+            Decoder::ParticleFilter,
+            Decoder::Viterbi,
+            Decoder::ForwardBackward,
+        ] {
+            let config = RunConfig {
+                decoder,
+                ..config.clone()
+            };
+            let decoding = decode(&observations, &config).unwrap();
+            assert_eq!(decoding.events[gap], StepEvent::Gap(GapPolicy::Split));
+
+            let column = |context: &ParticleContextType| {
+                let contexts = &decoding.posteriors.contexts;
+                contexts.iter().position(|c| c == context).unwrap()
+            };
+            let probs = &decoding.posteriors.probs[gap];
+            let go_fishing = probs[column(&ParticleContextType::go_fishing())];
+            assert!(
+                go_fishing < 1.0,
+                "{}: P(GoFishing) = {}",
+                decoder,
+                go_fishing
+            );
+            assert!(
+                probs[column(&fishing)] > 0.5,
+                "{}: P(Fishing) = {}",
+                decoder,
+                probs[column(&fishing)]
+            );
+        }
+    }
+
+    #[test]
+    fn decoding_with_an_invalid_configuration_fails() {
+        let config = RunConfig::default();
+        let observations = simulated_observations(&config).remove(0);
+
+        // A context of the graph that is not defined in the contexts, in a
+        // file of this test run only
+        let graph_path = std::env::temp_dir().join(format!(
+            "decoding_unknown_context_graph_{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(
+            &graph_path,
+            "GoFishing GoFishing 0.5\nGoFishing Unknown 0.5\nUnknown Unknown 1\n",
+        )
+        .unwrap();
+        let unknown_context = RunConfig {
+            graph_path: graph_path.to_string_lossy().into_owned(),
+            ..RunConfig::default()
+        };
+        let negative_sigma = RunConfig {
+            sigma: -1.0,
+            ..RunConfig::default()
+        };
+
+        let mut results = Vec::new();
+        for config in [unknown_context, negative_sigma] {
+            for decoder in [Decoder::ParticleFilter, Decoder::Viterbi] {
+                let config = RunConfig {
+                    decoder,
+                    ..config.clone()
+                };
+                results.push(decode(&observations, &config));
+            }
+        }
+        std::fs::remove_file(&graph_path).unwrap();
+
+        for result in results {
+            assert!(matches!(result, Err(Error::Config(_))));
+        }
+    }
+
+    #[test]
+    fn a_history_file_is_rejected_with_the_hmm_decoders() {
+        let config = RunConfig::default();
+        let observations = simulated_observations(&config).remove(0);
+
+        for decoder in [Decoder::Viterbi, Decoder::ForwardBackward] {
+            let mut config = RunConfig {
+                decoder,
+                ..RunConfig::default()
+            };
+            config.history.path = Some(String::from("history.csv"));
+
+            assert!(matches!(config.validate(), Err(Error::Config(_))));
+            assert!(matches!(
+                decode(&observations, &config),
+                Err(Error::Config(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn decoding_with_files_does_not_read_them_again() {
+        let config = RunConfig::default();
+        let observations = simulated_observations(&config).remove(0);
+
+        // A copy of the graph, removed once the files of the run are read
+        let graph_path = std::env::temp_dir().join(format!(
+            "decoding_read_once_graph_{}.txt",
+            std::process::id()
+        ));
+        std::fs::copy(&config.graph_path, &graph_path).unwrap();
+        let config = RunConfig {
+            graph_path: graph_path.to_string_lossy().into_owned(),
+            seed: Some(3),
+            ..config
+        };
+        let files = RunFiles::read(&config);
+        std::fs::remove_file(&graph_path).unwrap();
+        let files = files.unwrap();
+
+        for decoder in [
+            Decoder::ParticleFilter,
+            Decoder::Viterbi,
+            Decoder::ForwardBackward,
+        ] {
+            let config = RunConfig {
+                decoder,
+                ..config.clone()
+            };
+            let decoding = decode_with_files(&observations, &config, &files).unwrap();
+            assert_eq!(decoding.states.len(), observations.len());
+        }
+    }
+
+    #[test]
+    fn hmm_decoders_fail_when_no_context_can_explain_an_observation() {
+        // Every context forbids observations on the shore
+        let mut config = RunConfig::default();
+        for name in config.context_set().names() {
+            let penalty = ShorePenaltyConfig {
+                epsilon: 0.0,
+                d0: ShoreRange::Fixed(1000.0),
+            };
+            config.shore_penalty.insert(name, penalty);
+        }
+        let mut observations = simulated_observations(&config).remove(0);
+        observations[10].distance_to_shore = 0.0;
+
+        for decoder in [Decoder::Viterbi, Decoder::ForwardBackward] {
+            let config = RunConfig {
+                decoder,
+                ..config.clone()
+            };
+            assert!(matches!(
+                decode(&observations, &config),
+                Err(Error::Numerical(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::error::Error;
+use crate::utils::ln_gamma;
+use rand::Rng;
+use rand_distr::{Distribution, Gamma, LogNormal, Normal};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Distribution of the speed of a vessel in a given context. Log-normal and
+/// gamma distributions only produce positive speeds and usually fit the
+/// right-skewed speeds of fishing vessels better than a normal distribution.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "family", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum SpeedDistribution {
+    Normal { mean: f64, std_dev: f64 },
+    LogNormal { mu: f64, sigma: f64 },
+    Gamma { shape: f64, scale: f64 },
+}
+
+/// Families of distributions that can be fitted to speed samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistributionFamily {
+    Normal,
+    LogNormal,
+    Gamma,
+}
+
+impl SpeedDistribution {
+    /// Draws a speed. Fails if the parameters are invalid, see `validate`.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Result<f64, Error> {
+        let invalid = |e: &dyn fmt::Display| {
+            Error::Config(format!("invalid speed distribution {}: {}", self, e))
+        };
+        match *self {
+            SpeedDistribution::Normal { mean, std_dev } => Normal::new(mean, std_dev)
+                .map(|normal| normal.sample(rng))
+                .map_err(|e| invalid(&e)),
+            SpeedDistribution::LogNormal { mu, sigma } => LogNormal::new(mu, sigma)
+                .map(|log_normal| log_normal.sample(rng))
+                .map_err(|e| invalid(&e)),
+            SpeedDistribution::Gamma { shape, scale } => Gamma::new(shape, scale)
+                .map(|gamma| gamma.sample(rng))
+                .map_err(|e| invalid(&e)),
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        match *self {
+            SpeedDistribution::Normal { mean, .. } => mean,
+            SpeedDistribution::LogNormal { mu, sigma } => (mu + 0.5 * sigma * sigma).exp(),
+            SpeedDistribution::Gamma { shape, scale } => shape * scale,
+        }
+    }
+
+    /// Natural logarithm of the probability density at `x`.
+    pub fn ln_pdf(&self, x: f64) -> f64 {
+        let ln_sqrt_two_pi = 0.5 * (2.0 * std::f64::consts::PI).ln();
+        match *self {
+            SpeedDistribution::Normal { mean, std_dev } => {
+                -ln_sqrt_two_pi - std_dev.ln() - 0.5 * ((x - mean) / std_dev).powi(2)
+            }
+            SpeedDistribution::LogNormal { mu, sigma } => {
+                if x <= 0.0 {
+                    return f64::NEG_INFINITY;
+                }
+                -ln_sqrt_two_pi - sigma.ln() - x.ln() - 0.5 * ((x.ln() - mu) / sigma).powi(2)
+            }
+            SpeedDistribution::Gamma { shape, scale } => {
+                if x <= 0.0 {
+                    return f64::NEG_INFINITY;
+                }
+                (shape - 1.0) * x.ln() - x / scale - ln_gamma(shape) - shape * scale.ln()
+            }
+        }
+    }
+
+    /// Fits a distribution of the given family to speed samples. Log-normal
+    /// and gamma distributions are fitted on the positive samples only.
+    /// Returns `None` if there are not enough samples to fit the distribution.
+    pub fn fit(family: DistributionFamily, samples: &[f64]) -> Option<SpeedDistribution> {
+        match family {
+            DistributionFamily::Normal => {
+                let (mean, std_dev) = mean_and_std_dev(samples)?;
+                Some(SpeedDistribution::Normal { mean, std_dev })
+            }
+            DistributionFamily::LogNormal => {
+                let ln_samples: Vec<f64> = positive(samples).map(f64::ln).collect();
+                let (mu, sigma) = mean_and_std_dev(&ln_samples)?;
+                Some(SpeedDistribution::LogNormal { mu, sigma })
+            }
+            DistributionFamily::Gamma => {
+                // Maximum likelihood estimate of the shape with the
+                // approximation of Minka (2002)
+                let positive_samples: Vec<f64> = positive(samples).collect();
+                let (mean, _) = mean_and_std_dev(&positive_samples)?;
+                let mean_ln = positive_samples.iter().map(|x| x.ln()).sum::<f64>()
+                    / positive_samples.len() as f64;
+                let s = mean.ln() - mean_ln;
+                if !(s.is_finite() && s > 0.0) {
+                    return None;
+                }
+                let shape = (3.0 - s + ((s - 3.0).powi(2) + 24.0 * s).sqrt()) / (12.0 * s);
+                Some(SpeedDistribution::Gamma {
+                    shape,
+                    scale: mean / shape,
+                })
+            }
+        }
+    }
+
+    /// Mean log-likelihood of the samples under the distribution.
+    pub fn mean_ln_likelihood(&self, samples: &[f64]) -> f64 {
+        samples.iter().map(|&x| self.ln_pdf(x)).sum::<f64>() / samples.len() as f64
+    }
+
+    pub fn validate(&self, name: &str) -> Result<(), Error> {
+        let (location, spread) = match *self {
+            SpeedDistribution::Normal { mean, std_dev } => (mean, std_dev),
+            SpeedDistribution::LogNormal { mu, sigma } => (mu, sigma),
+            SpeedDistribution::Gamma { shape, scale } => {
+                if !(shape.is_finite() && shape > 0.0) {
+                    return Err(Error::Config(format!("{}.shape must be positive", name)));
+                }
+                (shape, scale)
+            }
+        };
+        if !location.is_finite() {
+            return Err(Error::Config(format!(
+                "{} parameters must be finite numbers",
+                name
+            )));
+        }
+        if !(spread.is_finite() && spread > 0.0) {
+            return Err(Error::Config(format!(
+                "{} spread parameter must be positive",
+                name
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn positive(samples: &[f64]) -> impl Iterator<Item = f64> + '_ {
+    samples.iter().copied().filter(|&x| x > 0.0)
+}
+
+fn mean_and_std_dev(samples: &[f64]) -> Option<(f64, f64)> {
+    if samples.len() < 2 {
+        return None;
+    }
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let std_dev = variance.sqrt();
+    if std_dev > 0.0 {
+        Some((mean, std_dev))
+    } else {
+        None
+    }
+}
+
+impl DistributionFamily {
+    pub const ALL: [DistributionFamily; 3] = [
+        DistributionFamily::Normal,
+        DistributionFamily::LogNormal,
+        DistributionFamily::Gamma,
+    ];
+}
+
+impl FromStr for DistributionFamily {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(DistributionFamily::Normal),
+            "log-normal" => Ok(DistributionFamily::LogNormal),
+            "gamma" => Ok(DistributionFamily::Gamma),
+            _ => Err(format!("Invalid distribution family: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for DistributionFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DistributionFamily::Normal => write!(f, "normal"),
+            DistributionFamily::LogNormal => write!(f, "log-normal"),
+            DistributionFamily::Gamma => write!(f, "gamma"),
+        }
+    }
+}
+
+impl fmt::Display for SpeedDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpeedDistribution::Normal { mean, std_dev } => {
+                write!(f, "normal(mean={:.3}, std_dev={:.3})", mean, std_dev)
+            }
+            SpeedDistribution::LogNormal { mu, sigma } => {
+                write!(f, "log-normal(mu={:.3}, sigma={:.3})", mu, sigma)
+            }
+            SpeedDistribution::Gamma { shape, scale } => {
+                write!(f, "gamma(shape={:.3}, scale={:.3})", shape, scale)
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors of context matching
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be read or written
+    Io { path: String, source: io::Error },
+    /// A CSV file could not be read or written
+    Csv { path: String, source: csv::Error },
+    /// A line of a graph file is malformed, `line` counting from 1
+    Graph {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    /// The run configuration or the arguments are invalid
+    Config(String),
+    /// The input data cannot be processed, e.g. a trajectory file without
+    /// any observation
+    Input(String),
+    /// A decoder could not compute the contexts, e.g. without any state
+    Numerical(String),
+}
+
+impl Error {
+    pub fn io(path: &str, source: io::Error) -> Error {
+        Error::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn csv(path: &str, source: csv::Error) -> Error {
+        Error::Csv {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "cannot access {}: {}", path, source),
+            Error::Csv { path, source } => write!(f, "CSV error in {}: {}", path, source),
+            Error::Graph {
+                path,
+                line,
+                message,
+            } => write!(f, "{}, line {}: {}", path, line, message),
+            Error::Config(message) => write!(f, "invalid configuration: {}", message),
+            Error::Input(message) | Error::Numerical(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Csv { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use crate::particle::ParticleContextType;
+use serde::Serialize;
+use std::fmt;
+use std::ops::Range;
+
+/// Contexts of the observations of one trajectory: the labels of its input
+/// file and the contexts of its result file
+#[derive(Debug, Clone)]
+pub struct LabelledTrajectory {
+    pub name: String,
+    pub truth: Vec<ParticleContextType>,
+    pub predicted: Vec<ParticleContextType>,
+}
+
+/// Scores of one trajectory
+#[derive(Debug, Clone, Serialize)]
+pub struct TrajectoryScores {
+    pub name: String,
+    pub nb_of_observations: usize,
+    pub purity: f64,
+    pub coverage: f64,
+    pub harmonic_mean: f64,
+    pub accuracy: f64,
+}
+
+/// Mean, minimum and maximum of a score over the trajectories
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ScoreSummary {
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Precision, recall and F1 score of one context over all the observations
+#[derive(Debug, Clone, Serialize)]
+pub struct ClassScores {
+    pub context: ParticleContextType,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    /// Number of observations labelled with this context
+    pub support: u64,
+}
+
+/// Number of observations of each labelled context given each predicted
+/// context
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfusionMatrix {
+    pub contexts: Vec<ParticleContextType>,
+    /// `counts[i][j]` is the number of observations labelled `contexts[i]`
+    /// and predicted `contexts[j]`
+    pub counts: Vec<Vec<u64>>,
+}
+
+/// Evaluation of a set of result files against the labels of their input
+/// files
+#[derive(Debug, Clone, Serialize)]
+pub struct EvaluationReport {
+    pub nb_of_trajectories: usize,
+    pub purity: ScoreSummary,
+    pub coverage: ScoreSummary,
+    pub harmonic_mean: ScoreSummary,
+    pub accuracy: ScoreSummary,
+    pub classes: Vec<ClassScores>,
+    pub confusion: ConfusionMatrix,
+    pub trajectories: Vec<TrajectoryScores>,
+}
+
+/// Ranges of the runs of consecutive observations in the same context.
+pub fn segments(contexts: &[ParticleContextType]) -> Vec<Range<usize>> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for i in 1..contexts.len() {
+        if contexts[i] != contexts[i - 1] {
+            segments.push(start..i);
+            start = i;
+        }
+    }
+    if start < contexts.len() {
+        segments.push(start..contexts.len());
+    }
+
+    segments
+}
+
+/// Mean fraction of the observations of each predicted segment whose label is
+/// the context of the segment. Segments without any correct observation are
+/// left out, as in `scripts/result_purity_and_coverage.py`.
+pub fn purity(truth: &[ParticleContextType], predicted: &[ParticleContextType]) -> f64 {
+    let fractions: Vec<f64> = segments(predicted)
+        .into_iter()
+        .map(|segment| {
+            let context = &predicted[segment.start];
+            let correct = truth[segment.clone()]
+                .iter()
+                .filter(|&label| label == context)
+                .count();
+            correct as f64 / segment.len() as f64
+        })
+        .filter(|&fraction| fraction > 0.0)
+        .collect();
+
+    mean(&fractions)
+}
+
+/// Mean fraction of the observations of each labelled segment that are
+/// predicted in the context of the segment.
+pub fn coverage(truth: &[ParticleContextType], predicted: &[ParticleContextType]) -> f64 {
+    let fractions: Vec<f64> = segments(truth)
+        .into_iter()
+        .map(|segment| {
+            let context = &truth[segment.start];
+            let covered = predicted[segment.clone()]
+                .iter()
+                .filter(|&prediction| prediction == context)
+                .count();
+            covered as f64 / segment.len() as f64
+        })
+        .collect();
+
+    mean(&fractions)
+}
+
+pub fn harmonic_mean(purity: f64, coverage: f64) -> f64 {
+    if purity + coverage > 0.0 {
+        2.0 * purity * coverage / (purity + coverage)
+    } else {
+        0.0
+    }
+}
+
+impl TrajectoryScores {
+    pub fn new(trajectory: &LabelledTrajectory) -> TrajectoryScores {
+        let purity = purity(&trajectory.truth, &trajectory.predicted);
+        let coverage = coverage(&trajectory.truth, &trajectory.predicted);
+        let correct = trajectory
+            .truth
+            .iter()
+            .zip(&trajectory.predicted)
+            .filter(|(label, prediction)| label == prediction)
+            .count();
+
+        TrajectoryScores {
+            name: trajectory.name.clone(),
+            nb_of_observations: trajectory.truth.len(),
+            purity,
+            coverage,
+            harmonic_mean: harmonic_mean(purity, coverage),
+            accuracy: ratio(correct as u64, trajectory.truth.len() as u64),
+        }
+    }
+}
+
+impl ScoreSummary {
+    fn new(values: &[f64]) -> ScoreSummary {
+        ScoreSummary {
+            mean: mean(values),
+            min: values.iter().copied().fold(f64::NAN, f64::min),
+            max: values.iter().copied().fold(f64::NAN, f64::max),
+        }
+    }
+}
+
+impl ConfusionMatrix {
+    pub fn new(contexts: Vec<ParticleContextType>) -> ConfusionMatrix {
+        let n = contexts.len();
+        ConfusionMatrix {
+            contexts,
+            counts: vec![vec![0; n]; n],
+        }
+    }
+
+    fn index(&mut self, context: &ParticleContextType) -> usize {
+        if let Some(i) = self.contexts.iter().position(|c| c == context) {
+            return i;
+        }
+        // Contexts missing from the context set, e.g. of another profile
+        self.contexts.push(context.clone());
+        for row in &mut self.counts {
+            row.push(0);
+        }
+        self.counts.push(vec![0; self.contexts.len()]);
+        self.contexts.len() - 1
+    }
+
+    pub fn add(&mut self, truth: &ParticleContextType, predicted: &ParticleContextType) {
+        let i = self.index(truth);
+        let j = self.index(predicted);
+        self.counts[i][j] += 1;
+    }
+
+    pub fn class_scores(&self) -> Vec<ClassScores> {
+        self.contexts
+            .iter()
+            .enumerate()
+            .map(|(i, context)| {
+                let true_positives = self.counts[i][i];
+                let support: u64 = self.counts[i].iter().sum();
+                let predicted: u64 = self.counts.iter().map(|row| row[i]).sum();
+                let precision = ratio(true_positives, predicted);
+                let recall = ratio(true_positives, support);
+
+                ClassScores {
+                    context: context.clone(),
+                    precision,
+                    recall,
+                    f1: harmonic_mean(precision, recall),
+                    support,
+                }
+            })
+            .collect()
+    }
+}
+
+impl EvaluationReport {
+    /// Scores the trajectories. `contexts` orders the rows of the confusion
+    /// matrix; contexts found in the trajectories only are appended to it.
+    pub fn new(
+        trajectories: &[LabelledTrajectory],
+        contexts: Vec<ParticleContextType>,
+    ) -> EvaluationReport {
+        let scores: Vec<TrajectoryScores> =
+            trajectories.iter().map(TrajectoryScores::new).collect();
+
+        let mut confusion = ConfusionMatrix::new(contexts);
+        for trajectory in trajectories {
+            for (truth, predicted) in trajectory.truth.iter().zip(&trajectory.predicted) {
+                confusion.add(truth, predicted);
+            }
+        }
+
+        let summary = |score: fn(&TrajectoryScores) -> f64| {
+            ScoreSummary::new(&scores.iter().map(score).collect::<Vec<f64>>())
+        };
+
+        EvaluationReport {
+            nb_of_trajectories: trajectories.len(),
+            purity: summary(|s| s.purity),
+            coverage: summary(|s| s.coverage),
+            harmonic_mean: summary(|s| s.harmonic_mean),
+            accuracy: summary(|s| s.accuracy),
+            classes: confusion.class_scores(),
+            confusion,
+            trajectories: scores,
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        return 0.0;
+    }
+    numerator as f64 / denominator as f64
+}
+
+impl fmt::Display for EvaluationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Evaluated {} trajectories.", self.nb_of_trajectories)?;
+        for (name, summary) in [
+            ("Purity", self.purity),
+            ("Coverage", self.coverage),
+            ("Harmonic mean", self.harmonic_mean),
+            ("Accuracy", self.accuracy),
+        ] {
+            writeln!(
+                f,
+                "{}: {:.1}% (min {:.1}%, max {:.1}%)",
+                name,
+                summary.mean * 100.0,
+                summary.min * 100.0,
+                summary.max * 100.0
+            )?;
+        }
+
+        writeln!(f, "\nPer context:")?;
+        for class in &self.classes {
+            writeln!(
+                f,
+                "  {}: precision {:.3}, recall {:.3}, F1 {:.3} ({} observations)",
+                class.context, class.precision, class.recall, class.f1, class.support
+            )?;
+        }
+
+        write!(
+            f,
+            "\nConfusion matrix (rows: labels, columns: predictions):"
+        )?;
+        write!(f, "\n  {:>12}", "")?;
+        for context in &self.confusion.contexts {
+            write!(f, " {:>12}", context.name())?;
+        }
+        for (context, row) in self.confusion.contexts.iter().zip(&self.confusion.counts) {
+            write!(f, "\n  {:>12}", context.name())?;
+            for count in row {
+                write!(f, " {:>12}", count)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    fn contexts(names: &str) -> Vec<ParticleContextType> {
+        names
+            .chars()
+            .map(|name| match name {
+                'S' => ParticleContextType::go_fishing(),
+                'F' => ParticleContextType::fishing(),
+                'P' => ParticleContextType::go_to_port(),
+                other => other.to_string().parse().unwrap(),
+            })
+            .collect()
+    }
+
+    fn trajectory(truth: &str, predicted: &str) -> LabelledTrajectory {
+        LabelledTrajectory {
+            name: String::from("trip"),
+            truth: contexts(truth),
+            predicted: contexts(predicted),
+        }
+    }
+
+    #[test]
+    fn segments_are_the_runs_of_a_context() {
+        assert_eq!(segments(&contexts("SSFFFS")), vec![0..2, 2..5, 5..6]);
+        assert_eq!(segments(&contexts("F")), vec![0..1]);
+        assert!(segments(&[]).is_empty());
+    }
+
+    #[test]
+    fn purity_and_coverage_of_the_segments() {
+        // Predicted segments: FF all correct, SSSS half correct
+        // Labelled segments: FFFF half covered, SS all covered
+        let (truth, predicted) = (contexts("FFFFSS"), contexts("FFSSSS"));
+        assert_close(purity(&truth, &predicted), 0.75);
+        assert_close(coverage(&truth, &predicted), 0.75);
+        assert_close(harmonic_mean(0.75, 0.75), 0.75);
+
+        // The predicted segment S has no correct observation and is left out
+        // of the purity, not of the coverage
+        let (truth, predicted) = (contexts("FF"), contexts("FS"));
+        assert_close(purity(&truth, &predicted), 1.0);
+        assert_close(coverage(&truth, &predicted), 0.5);
+        assert_close(harmonic_mean(1.0, 0.5), 2.0 / 3.0);
+    }
+
+    #[test]
+    fn confusion_counts_and_class_scores() {
+        let report = EvaluationReport::new(
+            &[trajectory("FFFFSS", "FFSSSS"), trajectory("SP", "SP")],
+            contexts("SFP"),
+        );
+
+        assert_eq!(report.confusion.contexts, contexts("SFP"));
+        assert_eq!(
+            report.confusion.counts,
+            vec![vec![3, 0, 0], vec![2, 2, 0], vec![0, 0, 1]]
+        );
+
+        let go_fishing = &report.classes[0];
+        assert_close(go_fishing.precision, 3.0 / 5.0);
+        assert_close(go_fishing.recall, 1.0);
+        assert_close(go_fishing.f1, 0.75);
+        assert_eq!(go_fishing.support, 3);
+
+        let fishing = &report.classes[1];
+        assert_close(fishing.precision, 1.0);
+        assert_close(fishing.recall, 0.5);
+        assert_close(fishing.f1, 2.0 / 3.0);
+        assert_eq!(fishing.support, 4);
+
+        let go_to_port = &report.classes[2];
+        assert_close(go_to_port.precision, 1.0);
+        assert_close(go_to_port.recall, 1.0);
+        assert_eq!(go_to_port.support, 1);
+
+        assert_eq!(report.nb_of_trajectories, 2);
+        assert_close(report.accuracy.mean, (4.0 / 6.0 + 1.0) / 2.0);
+        assert_close(report.accuracy.min, 4.0 / 6.0);
+        assert_close(report.accuracy.max, 1.0);
+    }
+
+    #[test]
+    fn contexts_missing_from_the_context_set_are_appended() {
+        let mut confusion = ConfusionMatrix::new(contexts("SF"));
+        confusion.add(&contexts("X")[0], &contexts("F")[0]);
+
+        assert_eq!(confusion.contexts, contexts("SFX"));
+        assert_eq!(
+            confusion.counts,
+            vec![vec![0, 0, 0], vec![0, 0, 0], vec![0, 1, 0]]
+        );
+    }
+
+    #[test]
+    fn a_single_context_is_scored_perfectly() {
+        let scores = TrajectoryScores::new(&trajectory("FFFF", "FFFF"));
+        assert_close(scores.purity, 1.0);
+        assert_close(scores.coverage, 1.0);
+        assert_close(scores.harmonic_mean, 1.0);
+        assert_close(scores.accuracy, 1.0);
+
+        let report = EvaluationReport::new(&[trajectory("FFFF", "FFFF")], contexts("SFP"));
+        let fishing = &report.classes[1];
+        assert_close(fishing.precision, 1.0);
+        assert_close(fishing.recall, 1.0);
+        assert_close(fishing.f1, 1.0);
+        // Contexts that never occur score 0 rather than NaN
+        for class in [&report.classes[0], &report.classes[2]] {
+            assert_eq!(class.support, 0);
+            assert_close(class.precision, 0.0);
+            assert_close(class.recall, 0.0);
+            assert_close(class.f1, 0.0);
+        }
+    }
+
+    #[test]
+    fn an_empty_trajectory_scores_0() {
+        let scores = TrajectoryScores::new(&trajectory("", ""));
+        assert_eq!(scores.nb_of_observations, 0);
+        assert_close(scores.purity, 0.0);
+        assert_close(scores.coverage, 0.0);
+        assert_close(scores.harmonic_mean, 0.0);
+        assert_close(scores.accuracy, 0.0);
+
+        let report = EvaluationReport::new(&[trajectory("", "")], contexts("SFP"));
+        assert!(report
+            .confusion
+            .counts
+            .iter()
+            .flatten()
+            .all(|&count| count == 0));
+        for class in &report.classes {
+            assert_close(class.precision, 0.0);
+            assert_close(class.recall, 0.0);
+            assert_close(class.f1, 0.0);
+        }
+    }
+}
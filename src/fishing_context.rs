@@ -1,423 +1,496 @@
-use crate::random_generator::{
-    random_normal, random_uniform, random_uniform_range, random_usize_uniform_range,
-};
+use crate::random_generator::random_seed;
 use crate::{
-    geometry::Point,
-    markov_graph::{read_graph_from_file, MarkovGraph},
-    observation::Observation,
+    collapse::{CollapseConfig, CollapsePolicy},
+    config::{HistoryConfig, RunConfig},
+    error::Error,
+    gap::{nb_of_steps, GapConfig, GapPolicy},
+    geometry::{Degrees, Point},
+    hmm::argmax,
+    lineage::Lineage,
+    markov_graph::MarkovGraph,
+    motion::MotionModel,
+    observation::{ContextPosteriors, Observation, StepEvent},
     particle::{Particle, ParticleContextType},
+    port::PortRegistry,
+    resampling::ResamplingScheme,
+    shore::ShorePenalty,
+    utils::ln_sum_exp,
 };
 
-use std::collections::HashMap;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{self, BufWriter, Write};
 
 #[derive(Debug)]
 pub struct FishingContext {
     observations: Vec<Observation>,
     nb_of_particles: u16,
     particles: Vec<Particle>,
+    /// Context history of the particles
+    lineage: Lineage,
     sigma: f64,
-    sailing_normal_speed_distr: (f64, f64),
-    fishing_normal_speed_distr: (f64, f64),
-    context_smoothing_window_size: usize,
+    resampling: ResamplingScheme,
+    /// Fraction of the number of particles under which the effective sample
+    /// size triggers a resampling
+    ess_threshold: f64,
+    /// Effective sample size of the particles after weighting, at each step
+    ess: Vec<f64>,
+    collapse: CollapseConfig,
+    /// Recoveries from weight collapses and gaps, at each step
+    events: Vec<StepEvent>,
+    gap: GapConfig,
+    /// Time step of the transitions of the Markov graph
+    time_step: f64,
+    /// Context states of the particles and their motion models
+    motion: MotionModel,
+    shore_penalties: BTreeMap<ParticleContextType, ShorePenalty>,
     markov_graph: MarkovGraph<ParticleContextType>,
-    is_record_history: bool,
-    history_file_path: String,
+    /// Probability of each context at the first observation, see `new`
+    prior: Option<Vec<(ParticleContextType, f64)>>,
+    history: HistoryConfig,
+    rng: ChaCha8Rng,
 }
 
 impl FishingContext {
+    /// Builds the particle filter of a trajectory from the run
+    /// configuration, its Markov graph and its port registry. The particles
+    /// start in the initial context of the set, or in contexts drawn from
+    /// `prior`, the probability of each context at the first observation.
     pub fn new(
         observations: &[Observation],
-        nb_of_particles: u16,
-        sigma: f64,
-        sailing_normal_speed_distr: (f64, f64),
-        fishing_normal_speed_distr: (f64, f64),
-        context_window_size: usize,
-        history_file: Option<String>,
-    ) -> FishingContext {
-        let markov_graph: MarkovGraph<ParticleContextType> = read_graph_from_file("src/graph.txt");
-        println!("\nHere is the Markov graph: \n{}", markov_graph);
-
-        FishingContext {
+        config: &RunConfig,
+        markov_graph: &MarkovGraph<ParticleContextType>,
+        ports: Option<&PortRegistry>,
+        prior: Option<&[(ParticleContextType, f64)]>,
+    ) -> Result<FishingContext, Error> {
+        if observations.is_empty() {
+            return Err(Error::Input(String::from(
+                "cannot run the particle filter without observations",
+            )));
+        }
+        let (home_port, destination) = config.ports.resolve(ports, observations);
+
+        Ok(FishingContext {
             observations: observations.to_vec(),
-            nb_of_particles: nb_of_particles,
+            nb_of_particles: config.nb_of_particles,
             particles: Vec::new(),
-            sigma: sigma,
-            sailing_normal_speed_distr: sailing_normal_speed_distr,
-            fishing_normal_speed_distr: fishing_normal_speed_distr,
-            context_smoothing_window_size: context_window_size,
-            markov_graph,
-            is_record_history: history_file.is_some(),
-            history_file_path: history_file.unwrap_or_default(),
-        }
+            lineage: Lineage::new(),
+            sigma: config.sigma,
+            resampling: config.resampling,
+            ess_threshold: config.ess_threshold,
+            ess: Vec::new(),
+            collapse: config.collapse,
+            events: Vec::new(),
+            gap: config.gap,
+            time_step: config.gap.resolve_time_step(observations),
+            motion: MotionModel {
+                contexts: config.context_set(),
+                home_port,
+                destination,
+            },
+            shore_penalties: config
+                .shore_penalty
+                .iter()
+                .map(|(context, penalty)| (context.clone(), penalty.resolve(observations)))
+                .collect(),
+            markov_graph: markov_graph.clone(),
+            prior: prior.map(<[_]>::to_vec),
+            history: config.history.clone(),
+            rng: ChaCha8Rng::seed_from_u64(config.seed.unwrap_or_else(random_seed)),
+        })
     }
 
-    pub fn particle_filter(&mut self) -> Vec<Observation> {
-        // Generate initial particles
-        for _i in 0..self.nb_of_particles {
-            let mut random_context = ParticleContextType::GoFishing;
-
-            let mut particle: Particle = Particle {
+    /// Runs the particle filter over the observations. Returns the most
+    /// probable context at each observation, i.e. the context with the largest
+    /// weighted fraction of the particle memories, with these fractions.
+    pub fn particle_filter(&mut self) -> Result<(Vec<Observation>, ContextPosteriors), Error> {
+        // Generate initial particles, in proportion to the prior
+        let n = self.nb_of_particles as usize;
+        let initial_contexts: Vec<ParticleContextType> = match &self.prior {
+            Some(prior) => {
+                let weights: Vec<f64> = prior.iter().map(|(_, prob)| *prob).collect();
+                ResamplingScheme::Systematic
+                    .resample(&weights, n, &mut self.rng)
+                    .into_iter()
+                    .map(|i| prior[i].0.clone())
+                    .collect()
+            }
+            None => vec![self.motion.contexts.initial(); n],
+        };
+        for random_context in initial_contexts {
+            let particle: Particle = Particle {
                 pos: self.observations[0].pos,
-                direction: Point {
-                    x: self.observations[0].heading.cos(),
-                    y: self.observations[0].heading.sin(),
-                },
-                heading: self.observations[0].heading,
+                direction: Point::from_bearing(Degrees(self.observations[0].heading)),
+                heading: Degrees(self.observations[0].heading),
+                turn: Degrees(0.0),
                 speed: self.observations[0].speed,
+                lineage: self.lineage.root(random_context.clone()),
                 context: random_context,
                 weight: 1.0 / self.nb_of_particles as f64,
-                memory: Vec::new(),
             };
-            particle.memory.push(random_context);
             self.particles.push(particle);
         }
+        self.events.push(StepEvent::None);
+        self.ess.push(self.effective_sample_size());
 
-        if self.is_record_history {
-            // Open history file and wrap the file in a buffered writer
-            let mut writer = BufWriter::new(
-                File::create(&self.history_file_path).expect("failed to create file"),
-            );
-
-            // Write headers to the file
-            let headers = format!(
-                "t,{}",
-                (1..=self.nb_of_particles)
-                    .map(|i| format!("p_{}", i))
-                    .collect::<Vec<_>>()
-                    .join(",")
-            );
-            writer
-                .write(headers.as_bytes())
-                .expect("failed to write to file");
-
-            // Add initial particles to history
-            write!(writer, "\n{}", 0).unwrap();
-            self.add_to_history(&mut writer, 0);
-
-            // Apply particle filtering for all observations
-            for i in 1..self.observations.len() {
-                self.particle_filter_steps(self.observations[i]);
-
-                // Add particles to history
-                if i < 50
-                    || (i > (self.observations.len() / 2) - 100
-                        && i < (self.observations.len() / 2))
-                    || (i > self.observations.len() - 50 && i < self.observations.len())
-                {
-                    write!(writer, "\n{}", i).unwrap();
-                    self.add_to_history(&mut writer, i);
-                    writer.flush().expect("failed to flush buffer");
-                }
-            }
-
-            // Flush the buffer to ensure that any remaining data is written to the file
-            writer.flush().expect("failed to flush buffer");
+        if let Some(history_file_path) = self.history.path.clone() {
+            self.filter_with_history(&history_file_path)?;
         } else {
             // Apply particle filtering for all observations
             for i in 1..self.observations.len() {
-                self.particle_filter_steps(self.observations[i]);
+                let observation = self.observations[i].clone();
+                self.particle_filter_steps(&observation)?;
             }
         }
 
         self.calc_optimal_sequence()
     }
 
-    fn add_to_history(&self, writer: &mut BufWriter<File>, counter: usize) {
+    /// Applies the particle filter to all the observations, recording the
+    /// particle memories of the steps selected by the history options.
+    fn filter_with_history(&mut self, history_file_path: &str) -> Result<(), Error> {
+        let io_error = |e| Error::io(history_file_path, e);
+
+        // Open history file and wrap the file in a buffered writer
+        let mut writer = BufWriter::new(File::create(history_file_path).map_err(io_error)?);
+
+        // Write headers to the file
+        let headers = format!(
+            "t,ess,{}",
+            (1..=self.nb_of_particles)
+                .map(|i| format!("p_{}", i))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        writer.write_all(headers.as_bytes()).map_err(io_error)?;
+
+        // Add initial particles to history
+        self.add_to_history(&mut writer, 0).map_err(io_error)?;
+
+        // Apply particle filtering for all observations
+        let len = self.observations.len();
+        for i in 1..len {
+            let observation = self.observations[i].clone();
+            self.particle_filter_steps(&observation)?;
+
+            // Add particles to history
+            if i < self.history.head
+                || (i > (len / 2).saturating_sub(self.history.middle) && i < (len / 2))
+                || (i > len.saturating_sub(self.history.tail) && i < len)
+            {
+                self.add_to_history(&mut writer, i).map_err(io_error)?;
+                writer.flush().map_err(io_error)?;
+            }
+        }
+
+        // Flush the buffer to ensure that any remaining data is written to the file
+        writer.flush().map_err(io_error)
+    }
+
+    /// Writes the particle memories of the steps up to `counter`.
+    fn add_to_history(&self, writer: &mut BufWriter<File>, counter: usize) -> io::Result<()> {
+        write!(writer, "\n{}", counter)?;
+        let memories = self.particle_memories();
         for i in 0..counter + 1 {
-            for particle in &self.particles {
-                write!(writer, ",{}", particle.memory[i]).unwrap();
+            write!(writer, ",{}", self.ess[i])?;
+            for memory in &memories {
+                write!(writer, ",{}", memory[i])?;
             }
             if i != counter {
-                write!(writer, "\n{}", counter).unwrap();
+                write!(writer, "\n{}", counter)?;
             }
         }
-        // write!(writer, "\n").unwrap();
+        Ok(())
     }
 
-    fn particle_filter_steps(&mut self, observation: Observation) {
-        // Importance sampling
-        self.particles = self.resample();
+    fn particle_filter_steps(&mut self, observation: &Observation) -> Result<(), Error> {
+        if self.gap.policy == GapPolicy::Reinitialize && self.gap.is_gap(observation) {
+            self.cross_gap(observation);
+            return Ok(());
+        }
+
+        // Importance sampling, only once the weights have degenerated
+        if self.needs_resampling() {
+            self.particles = self.resample();
+        }
+        // Particles before the step, restored if the observation is skipped
+        let previous = self.particles.clone();
 
         // Update/Drift & Diffuse
         for i in 0..self.particles.len() {
             // Drawing a sample context-state based on transition probabilities
-            // 10% chance that the context changes to another one
-            let mut new_context: ParticleContextType = self.particles[i].context;
-            if random_uniform() < 0.1 {
-                new_context = self.markov_graph.get_dest(new_context, 0.1).unwrap();
-            }
+            let new_context: ParticleContextType = self
+                .markov_graph
+                .sample_dest(&self.particles[i].context, &mut self.rng)
+                .unwrap_or_else(|| self.particles[i].context.clone());
 
             // Add context to memory
+            self.particles[i].lineage = self
+                .lineage
+                .extend(self.particles[i].lineage, new_context.clone());
             self.particles[i].context = new_context;
-            self.particles[i].memory.push(new_context);
 
             // Applying the motion model to generate new particle based on
             // previous one and drawn sample context-state above
-            self.particles[i] = self.update(observation, &self.particles[i]);
+            self.particles[i] =
+                self.motion
+                    .update(&self.particles[i], observation.time, &mut self.rng)?;
         }
 
         // Assigning weights
-        self.particles = self.weight_measurement(&observation);
-        let weight_sum = self.particles.iter().map(|p| p.weight).sum::<f64>();
-        self.particles
-            .iter_mut()
-            .for_each(|p| p.weight /= weight_sum);
+        let event = self.weight_measurement(observation);
+        if event == StepEvent::Collapse(CollapsePolicy::Skip) {
+            self.restore(previous);
+        }
+        self.events.push(event);
+        self.ess.push(self.effective_sample_size());
+        Ok(())
     }
 
-    fn resample(&mut self) -> Vec<Particle> {
-        let mut new_particles: Vec<Particle> = Vec::new();
-        let mut t = 0.0f64;
-        let mut k: Vec<f64> = vec![0.0; self.particles.len()];
+    /// Puts the particles back as they were before a skipped step, with their
+    /// weights. Each particle keeps its context, which is its memory of the
+    /// skipped observation.
+    fn restore(&mut self, previous: Vec<Particle>) {
+        self.particles = previous
+            .into_iter()
+            .map(|particle| Particle {
+                lineage: self
+                    .lineage
+                    .extend(particle.lineage, particle.context.clone()),
+                ..particle
+            })
+            .collect();
 
-        for i in 0..self.particles.len() {
-            t += self.particles[i].weight;
-            k[i] = t;
-        }
+        let ln_weights: Vec<f64> = self.particles.iter().map(|p| p.weight.ln()).collect();
+        self.set_weights(&ln_weights);
+    }
 
-        for _ in 0..self.nb_of_particles {
-            let mut t2 = 0.0f64;
-            if t > 0.0 {
-                t2 = random_uniform_range(0.0, t);
-            }
+    /// Crosses a gap: the contexts of the particles are drawn from the
+    /// transitions of the graph over the duration of the gap, and the
+    /// particles are moved to the observation after it.
+    fn cross_gap(&mut self, observation: &Observation) {
+        self.particles = self.resample();
 
-            let mut j: usize = 0;
-            while k[j] < t2 {
-                j += 1;
-            }
-            new_particles.push(self.particles[j].clone());
+        let graph = self
+            .markov_graph
+            .power(nb_of_steps(observation.time, self.time_step));
+        for particle in &mut self.particles {
+            let new_context = graph
+                .sample_dest(&particle.context, &mut self.rng)
+                .unwrap_or_else(|| particle.context.clone());
+            particle.lineage = self.lineage.extend(particle.lineage, new_context.clone());
+            particle.context = new_context;
         }
 
-        new_particles
+        self.reinitialize(observation);
+        self.weight_measurement(observation);
+        self.events.push(StepEvent::Gap(GapPolicy::Reinitialize));
+        self.ess.push(self.effective_sample_size());
     }
 
-    fn update(&self, observation: Observation, particle: &Particle) -> Particle {
-        let time_diff = observation.time;
-
-        // Update speed
-        let new_speed = match particle.context {
-            ParticleContextType::Fishing => random_normal(
-                self.fishing_normal_speed_distr.0,
-                self.fishing_normal_speed_distr.1,
-            ),
-            ParticleContextType::GoFishing => random_normal(
-                self.sailing_normal_speed_distr.0,
-                self.sailing_normal_speed_distr.1,
-            ),
-            ParticleContextType::GoToPort => random_normal(
-                self.sailing_normal_speed_distr.0,
-                self.sailing_normal_speed_distr.1,
-            ),
-        };
-        let distance = new_speed * time_diff;
-
-        // Update heading
-        let new_heading = self.generate_new_random_heading(&particle);
-
-        // Update direction
-        let new_dir = self.calc_new_direction(new_heading);
+    /// Events of each step of the last run of the filter.
+    pub fn events(&self) -> &[StepEvent] {
+        &self.events
+    }
 
-        // Update position
-        let new_pos = Point {
-            x: particle.pos.x + (distance * new_dir.x),
-            y: particle.pos.y + (distance * new_dir.y),
-        };
+    /// Effective sample size of the normalized weights, `1 / sum(w^2)`,
+    /// between 1 when one particle has all the weight and the number of
+    /// particles when the weights are uniform.
+    fn effective_sample_size(&self) -> f64 {
+        1.0 / self.particles.iter().map(|p| p.weight.powi(2)).sum::<f64>()
+    }
 
-        Particle {
-            pos: new_pos,
-            direction: new_dir,
-            heading: new_heading,
-            speed: new_speed,
-            weight: particle.weight,
-            context: particle.context,
-            memory: particle.memory.clone(),
+    fn needs_resampling(&self) -> bool {
+        if self.ess_threshold >= 1.0 {
+            return true;
         }
+        // Degenerate weights give a NaN effective sample size
+        let ess = self.ess.last().copied().unwrap_or(f64::NAN);
+        ess.is_nan() || ess < self.ess_threshold * self.nb_of_particles as f64
     }
 
-    fn weight_measurement(&self, observation: &Observation) -> Vec<Particle> {
-        self.particles
-            .iter()
-            .map(|particle| {
-                let weight = self.calc_emission_prob(observation, particle);
-                Particle {
-                    weight,
-                    memory: particle.memory.clone(),
-                    ..*particle
-                }
+    fn resample(&mut self) -> Vec<Particle> {
+        let weights: Vec<f64> = self.particles.iter().map(|p| p.weight).collect();
+
+        self.resampling
+            .resample(&weights, self.nb_of_particles as usize, &mut self.rng)
+            .into_iter()
+            .map(|j| Particle {
+                // Offspring start with equal weights
+                weight: 1.0,
+                ..self.particles[j].clone()
             })
             .collect()
     }
 
-    fn generate_new_random_heading(&self, particle: &Particle) -> f64 {
-        let coast = self.observations[0].pos;
-
-        if particle.pos.x == coast.x && particle.pos.y == coast.y {
-            let heading_low = particle.heading - 0.4;
-            let heading_high = particle.heading + 0.4;
-
-            return random_uniform_range(heading_low, heading_high);
+    /// Weights the particles with the likelihood of the observation. Weights
+    /// are computed and normalized in log space so that observations far
+    /// from every particle do not underflow them to zero, and a collapse of
+    /// the weights is recovered from with the configured policy.
+    fn weight_measurement(&mut self, observation: &Observation) -> StepEvent {
+        let mut sigma = self.sigma;
+        let mut ln_weights = self.ln_weights(observation, sigma);
+        if !self.is_collapsed(observation, &ln_weights, sigma) {
+            self.set_weights(&ln_weights);
+            return StepEvent::None;
         }
 
-        let theta = ((coast.y - particle.pos.y) / (coast.x - particle.pos.x)).atan();
-
-        let new_heading: f64 = match particle.context {
-            ParticleContextType::Fishing => {
-                random_uniform_range(particle.heading - 0.4, particle.heading + 0.4)
-            }
-            ParticleContextType::GoFishing => {
-                let heading_diff =
-                    (theta - particle.heading.to_radians()) % (2.0 * std::f64::consts::PI);
-
-                let low: f64;
-                let high: f64;
-
-                if heading_diff < std::f64::consts::PI {
-                    low = 0.0;
-                    high = 22.0;
-                } else {
-                    low = -22.0;
-                    high = 0.0;
+        let mut policy = self.collapse.policy;
+        if policy == CollapsePolicy::InflateSigma {
+            for _ in 0..self.collapse.max_inflations {
+                sigma *= self.collapse.sigma_inflation;
+                ln_weights = self.ln_weights(observation, sigma);
+                if !self.is_collapsed(observation, &ln_weights, sigma) {
+                    break;
                 }
+            }
+            if self.is_collapsed(observation, &ln_weights, sigma) {
+                policy = CollapsePolicy::Reinitialize;
+            }
+        }
 
-                random_uniform_range(low, high) + particle.heading
+        match policy {
+            CollapsePolicy::Reinitialize => self.reinitialize(observation),
+            CollapsePolicy::InflateSigma => self.set_weights(&ln_weights),
+            CollapsePolicy::Skip => {
+                let ln_weights: Vec<f64> = self.particles.iter().map(|p| p.weight.ln()).collect();
+                self.set_weights(&ln_weights);
             }
-            ParticleContextType::GoToPort => {
-                let heading_diff =
-                    (theta - particle.heading.to_radians()) % (2.0 * std::f64::consts::PI);
-
-                let low: f64;
-                let high: f64;
-
-                if heading_diff < std::f64::consts::PI {
-                    low = -22.0;
-                    high = 0.0;
-                } else {
-                    low = 0.0;
-                    high = 22.0;
+        }
+
+        StepEvent::Collapse(policy)
+    }
+
+    /// Unnormalized log weights of the particles after the observation.
+    fn ln_weights(&self, observation: &Observation, sigma: f64) -> Vec<f64> {
+        self.particles
+            .iter()
+            .map(|particle| {
+                // Weights accumulate over the steps without resampling
+                let mut ln_weight =
+                    particle.weight.ln() + self.calc_ln_emission_prob(observation, particle, sigma);
+                if let Some(penalty) = self.shore_penalties.get(&particle.context) {
+                    ln_weight += penalty.weight_scale(observation.distance_to_shore).ln();
                 }
+                ln_weight
+            })
+            .collect()
+    }
 
-                random_uniform_range(low, high) + particle.heading
-            }
-        };
+    /// The weights collapsed if no particle can explain the observation, or
+    /// if the nearest particle is too far from it.
+    fn is_collapsed(&self, observation: &Observation, ln_weights: &[f64], sigma: f64) -> bool {
+        if !ln_sum_exp(ln_weights.iter().copied()).is_finite() {
+            return true;
+        }
 
-        new_heading % 360.0
+        let max_distance = self.collapse.max_distance_sigmas * sigma;
+        max_distance.is_finite()
+            && self
+                .particles
+                .iter()
+                .all(|particle| (observation.pos - particle.pos).norm() > max_distance)
     }
 
-    fn calc_new_direction(&self, heading: f64) -> Point {
-        let mut new_dir = Point {
-            x: heading.cos(),
-            y: heading.sin(),
-        };
+    /// Normalizes the log weights into the weights of the particles.
+    fn set_weights(&mut self, ln_weights: &[f64]) {
+        let ln_total = ln_sum_exp(ln_weights.iter().copied());
+        for (particle, ln_weight) in self.particles.iter_mut().zip(ln_weights) {
+            particle.weight = (ln_weight - ln_total).exp();
+        }
+    }
 
-        new_dir * (1.0 / new_dir.norm())
+    /// Moves every particle to the observation with uniform weights. The
+    /// particles keep their context and lineage.
+    fn reinitialize(&mut self, observation: &Observation) {
+        let weight = 1.0 / self.particles.len() as f64;
+        for particle in &mut self.particles {
+            particle.pos = observation.pos;
+            particle.direction = Point::from_bearing(Degrees(observation.heading));
+            particle.heading = Degrees(observation.heading);
+            particle.speed = observation.speed;
+            particle.weight = weight;
+        }
     }
 
-    fn calc_optimal_sequence(&self) -> Vec<Observation> {
-        let mut smoothing_window: Vec<(u16, u16, u16)> = Vec::new();
+    /// Contexts of every particle at each step so far, reconstructed from
+    /// their lineage.
+    fn particle_memories(&self) -> Vec<Vec<ParticleContextType>> {
+        self.particles
+            .iter()
+            .map(|particle| self.lineage.path(particle.lineage))
+            .collect()
+    }
 
+    fn calc_optimal_sequence(&self) -> Result<(Vec<Observation>, ContextPosteriors), Error> {
         let mut optimal_sequence: Vec<Observation> = Vec::new();
 
-        let states = self.markov_graph.get_all_nodes();
+        let states = self
+            .motion
+            .contexts
+            .order(&self.markov_graph.get_all_nodes());
+        if states.is_empty() {
+            return Err(Error::Numerical(String::from(
+                "no context of the graph is in the context set",
+            )));
+        }
+        let memories = self.particle_memories();
+        // Degenerate weights fall back to counting the particles
+        let total_weight: f64 = self.particles.iter().map(|p| p.weight).sum();
+        let weights: Vec<f64> = if total_weight.is_finite() && total_weight > 0.0 {
+            self.particles
+                .iter()
+                .map(|p| p.weight / total_weight)
+                .collect()
+        } else {
+            vec![1.0 / self.particles.len() as f64; self.particles.len()]
+        };
+        let mut posteriors = ContextPosteriors {
+            contexts: states.clone(),
+            probs: Vec::with_capacity(self.observations.len()),
+        };
 
         for i in 0..self.observations.len() {
-            let obs_memory: Vec<ParticleContextType> =
-                self.particles.iter().map(|p| p.memory[i]).collect();
-
-            let mut states_count: HashMap<ParticleContextType, u16> = states
-                .clone()
-                .into_iter()
-                .fold(HashMap::new(), |mut acc, ctx_type| {
-                    acc.insert(ctx_type, 0);
-                    acc
-                });
-
-            for memory in obs_memory {
-                states_count
-                    .entry(memory)
-                    .and_modify(|count| *count += 1)
-                    .or_insert(1);
+            // The final weights of the particles weight their whole lineage
+            let mut probs = vec![0.0; states.len()];
+            for (memory, weight) in memories.iter().zip(&weights) {
+                if let Some(j) = states.iter().position(|state| *state == memory[i]) {
+                    probs[j] += weight;
+                }
             }
 
-            // Majority context
-            let (majority_context, _) = states_count
-                .iter()
-                .max_by_key(|&(_, v)| v)
-                .expect("Map is empty");
+            // Most probable context
+            let context = states[argmax(&probs)].clone();
+            posteriors.probs.push(probs);
 
             let obs_with_context = Observation {
                 pos: self.observations[i].pos,
                 time: self.observations[i].time,
                 heading: self.observations[i].heading,
                 speed: self.observations[i].speed,
-                context: *majority_context,
+                turn: self.observations[i].turn,
+                distance_to_shore: self.observations[i].distance_to_shore,
+                context,
             };
             optimal_sequence.push(obs_with_context);
-
-            // // For smoothing
-            // let context_window_elem = (go_fishing_count, fishing_count, go_to_port_count);
-            // if smoothing_window.len() < self.context_smoothing_window_size {
-            //     smoothing_window.push(context_window_elem);
-            // } else {
-            //     smoothing_window.drain(0..1);
-            //     smoothing_window.push(context_window_elem);
-            // }
-
-            // // Smoothing
-            // if i >= self.context_smoothing_window_size {
-            //     let current_context =
-            //         optimal_sequence[i - (self.context_smoothing_window_size / 2)].context;
-            //     let smoothed_context =
-            //         self.smooth_context(current_context, smoothing_window.as_slice());
-            //     optimal_sequence[i - (self.context_smoothing_window_size / 2)].context =
-            //         smoothed_context;
-            // }
         }
 
-        optimal_sequence
+        Ok((optimal_sequence, posteriors))
     }
 
-    fn smooth_context(
+    /// Log density of the distance between the observation and the particle
+    /// under a centered normal distribution of standard deviation `sigma`.
+    fn calc_ln_emission_prob(
         &self,
-        current_context: ParticleContextType,
-        smoothing_window: &[(u16, u16, u16)],
-    ) -> ParticleContextType {
-        let mid_index = self.context_smoothing_window_size / 2;
-
-        let (go_fishing_total, fishing_total, go_to_port_total) = smoothing_window
-            .iter()
-            .take(mid_index)
-            .chain(smoothing_window.iter().skip(mid_index + 1))
-            .fold((0, 0, 0), |(gf, f, gp), &(gfi, fi, gpi)| {
-                (gf + gfi, f + fi, gp + gpi)
-            });
-
-        if smoothing_window[mid_index].0 >= 50
-            && go_fishing_total > fishing_total
-            && go_fishing_total > go_to_port_total
-        {
-            return ParticleContextType::GoFishing;
-        } else if smoothing_window[mid_index].1 >= 50
-            && fishing_total > go_fishing_total
-            && fishing_total > go_to_port_total
-        {
-            return ParticleContextType::Fishing;
-        } else if smoothing_window[mid_index].2 >= 50
-            && go_to_port_total > go_fishing_total
-            && go_to_port_total > fishing_total
-        {
-            return ParticleContextType::GoToPort;
-        }
-
-        current_context
-    }
-
-    fn calc_emission_prob(&self, observation: &Observation, particle: &Particle) -> f64 {
+        observation: &Observation,
+        particle: &Particle,
+        sigma: f64,
+    ) -> f64 {
         let p: Point = observation.pos - particle.pos;
         let two_pi = 2.0f64 * std::f64::consts::PI;
         let gc = p.norm();
-        let first_term = 1.0 / (two_pi.sqrt() * self.sigma);
-        let second_term = -0.5 * (gc / self.sigma).powf(2.0);
-        first_term * second_term.exp()
+        -(two_pi.sqrt() * sigma).ln() - 0.5 * (gc / sigma).powf(2.0)
     }
 }
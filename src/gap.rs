@@ -0,0 +1,117 @@
+use crate::error::Error;
+use crate::observation::Observation;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Handling of the outages of the AIS tracks, when the time since the
+/// previous observation is too long for the motion model to be meaningful.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GapConfig {
+    /// Observations whose `time_gap` is above this threshold start a gap.
+    /// Without it, gaps are not handled
+    pub max_time_gap: Option<f64>,
+    pub policy: GapPolicy,
+    /// Time step of the transition probabilities of the Markov graph, used by
+    /// the `reinitialize` policy. The median `time_gap` of the trajectory by
+    /// default
+    pub time_step: Option<f64>,
+}
+
+/// What to do at a gap
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum GapPolicy {
+    /// Split the trajectory into segments decoded separately, each one
+    /// starting from the contexts at the end of the previous one after the
+    /// transitions of the graph over the gap
+    #[default]
+    Split,
+    /// Move the particles to the observation after the gap and draw their
+    /// context from the transition probabilities of the graph over the
+    /// duration of the gap
+    Reinitialize,
+}
+
+impl GapConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self
+            .max_time_gap
+            .is_some_and(|max| !(max.is_finite() && max > 0.0))
+        {
+            return Err(Error::Config("gap.max_time_gap must be positive".into()));
+        }
+        if self
+            .time_step
+            .is_some_and(|step| !(step.is_finite() && step > 0.0))
+        {
+            return Err(Error::Config("gap.time_step must be positive".into()));
+        }
+        Ok(())
+    }
+
+    /// Whether the observation comes after a gap. The first observation of a
+    /// trajectory never does.
+    pub fn is_gap(&self, observation: &Observation) -> bool {
+        self.max_time_gap.is_some_and(|max| observation.time > max)
+    }
+
+    /// Ranges of the observations between the gaps of a trajectory.
+    pub fn segments(&self, observations: &[Observation]) -> Vec<Range<usize>> {
+        let mut segments = Vec::new();
+        let mut start = 0;
+        for (i, observation) in observations.iter().enumerate().skip(1) {
+            if self.is_gap(observation) {
+                segments.push(start..i);
+                start = i;
+            }
+        }
+        if start < observations.len() {
+            segments.push(start..observations.len());
+        }
+
+        segments
+    }
+
+    /// Time step of the transitions of the graph for a trajectory.
+    pub fn resolve_time_step(&self, observations: &[Observation]) -> f64 {
+        self.time_step.unwrap_or_else(|| {
+            let mut time_gaps: Vec<f64> = observations
+                .iter()
+                .skip(1)
+                .map(|observation| observation.time)
+                .filter(|time| time.is_finite() && *time > 0.0)
+                .collect();
+            time_gaps.sort_by(f64::total_cmp);
+            time_gaps.get(time_gaps.len() / 2).copied().unwrap_or(1.0)
+        })
+    }
+}
+
+/// Number of transitions of the graph during a gap, at least one.
+pub fn nb_of_steps(time_gap: f64, time_step: f64) -> u32 {
+    (time_gap / time_step).round().clamp(1.0, u32::MAX as f64) as u32
+}
+
+impl FromStr for GapPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "split" => Ok(GapPolicy::Split),
+            "reinitialize" => Ok(GapPolicy::Reinitialize),
+            _ => Err(format!("Invalid gap policy: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for GapPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GapPolicy::Split => write!(f, "split"),
+            GapPolicy::Reinitialize => write!(f, "reinitialize"),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::context::{ContextConfig, HeadingModel};
+use crate::distribution::SpeedDistribution;
+use crate::error::Error;
+use crate::observation::AisRecord;
+use crate::particle::ParticleContextType;
+use crate::shore::ShorePenaltyConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Names of the built-in gear profiles
+pub const BUILTIN_PROFILES: [&str; 4] = ["trawler", "longliner", "purse-seiner", "gillnetter"];
+
+/// Selection of the gear profile of each vessel. The gear of a vessel is
+/// taken from the vessel registry, then from the `gear` column of its AIS
+/// records, then from `default`. Vessels without a gear are processed with
+/// the run configuration itself.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GearConfig {
+    /// CSV file of the vessel registry, with `id` and `gear` columns
+    pub registry: Option<String>,
+    /// Profile of the vessels whose gear is unknown
+    pub default: Option<String>,
+}
+
+/// Behaviour of the vessels of a gear type: their context states with their
+/// motion models and, optionally, their own Markov graph and shore penalties.
+/// Missing parts are taken from the run configuration.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GearProfile {
+    pub contexts: Vec<ContextConfig>,
+    pub graph_path: Option<String>,
+    pub shore_penalty: Option<BTreeMap<ParticleContextType, ShorePenaltyConfig>>,
+}
+
+/// Gear type of each vessel, read from a CSV file with `id` and `gear`
+/// columns
+#[derive(Debug, Clone, Default)]
+pub struct VesselRegistry {
+    gears: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VesselRecord {
+    id: String,
+    gear: String,
+}
+
+impl GearConfig {
+    /// Reads the vessel registry, empty without a registry file.
+    pub fn load_registry(&self) -> Result<VesselRegistry, Error> {
+        match &self.registry {
+            Some(path) => VesselRegistry::from_csv(path),
+            None => Ok(VesselRegistry::default()),
+        }
+    }
+
+    /// Gear of a vessel, `None` when it is unknown and there is no default.
+    pub fn gear_of(
+        &self,
+        id: &str,
+        records: &[AisRecord],
+        registry: &VesselRegistry,
+    ) -> Option<String> {
+        registry
+            .get(id)
+            .map(String::from)
+            .or_else(|| {
+                records
+                    .iter()
+                    .find_map(|record| record.gear.clone().filter(|gear| !gear.is_empty()))
+            })
+            .or_else(|| self.default.clone())
+    }
+}
+
+impl VesselRegistry {
+    pub fn from_csv(filename: &str) -> Result<VesselRegistry, Error> {
+        let mut rdr = csv::Reader::from_path(filename).map_err(|e| Error::csv(filename, e))?;
+        let mut gears = HashMap::new();
+        for result in rdr.deserialize() {
+            let record: VesselRecord = result.map_err(|e| Error::csv(filename, e))?;
+            gears.insert(record.id, record.gear);
+        }
+
+        Ok(VesselRegistry { gears })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&str> {
+        self.gears.get(id).map(String::as_str)
+    }
+}
+
+/// Built-in profile of a gear type. They share the GoFishing, Fishing and
+/// GoToPort contexts and the Markov graph of the run, and differ by their
+/// motion models, which are rough starting points to refit with
+/// `fit-motion --profile`.
+pub fn builtin_profile(name: &str) -> Option<GearProfile> {
+    let (sailing_speed, fishing_speed, fishing_heading) = match name {
+        // Slow random walk while towing the trawl
+        "trawler" => (
+            normal(3.31, 1.19),
+            normal(1.36, 0.89),
+            // 0.4 radians
+            HeadingModel::RandomWalk { jitter: 22.9 },
+        ),
+        // Straight lines while setting, slow while hauling
+        "longliner" => (
+            normal(3.5, 1.2),
+            normal(2.0, 1.0),
+            HeadingModel::RandomWalk { jitter: 5.0 },
+        ),
+        // Circles around the school while setting the seine
+        "purse-seiner" => (
+            normal(4.0, 1.3),
+            normal(1.0, 0.8),
+            HeadingModel::Circling {
+                rate: 20.0,
+                jitter: 10.0,
+            },
+        ),
+        // Nearly drifting along the nets, with frequent heading changes
+        "gillnetter" => (
+            normal(3.0, 1.1),
+            normal(0.6, 0.5),
+            HeadingModel::RandomWalk { jitter: 30.0 },
+        ),
+        _ => return None,
+    };
+
+    Some(GearProfile {
+        contexts: vec![
+            ContextConfig {
+                name: ParticleContextType::go_fishing(),
+                labels: vec![String::from("01-sailing")],
+                speed: sailing_speed,
+                heading: HeadingModel::AwayFromPort { jitter: 22.0 },
+            },
+            ContextConfig {
+                name: ParticleContextType::fishing(),
+                labels: vec![String::from("02-fishing")],
+                speed: fishing_speed,
+                heading: fishing_heading,
+            },
+            ContextConfig {
+                name: ParticleContextType::go_to_port(),
+                labels: vec![String::from("03-sailing")],
+                speed: sailing_speed,
+                heading: HeadingModel::TowardPort { jitter: 22.0 },
+            },
+        ],
+        graph_path: None,
+        shore_penalty: None,
+    })
+}
+
+fn normal(mean: f64, std_dev: f64) -> SpeedDistribution {
+    SpeedDistribution::Normal { mean, std_dev }
+}
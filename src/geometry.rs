@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::cmp::{Eq, Ord, Ordering};
 use std::fmt;
-use std::ops::{Add, Div, Mul, Sub};
+use std::ops::{Add, Div, Mul, Neg, Sub};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct Point {
@@ -9,12 +9,40 @@ pub struct Point {
     pub y: f64,
 }
 
+/// Angle in degrees. Headings are compass bearings, clockwise from north,
+/// i.e. from the `y` axis toward the `x` axis, like the `bearing` column of
+/// the AIS files.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, PartialOrd, Default)]
+#[serde(transparent)]
+pub struct Degrees(pub f64);
+
+/// Angle in radians
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, PartialOrd, Default)]
+#[serde(transparent)]
+pub struct Radians(pub f64);
+
 impl Point {
     pub fn norm(&self) -> f64 {
         let f = self.x.powf(2.0) + self.y.powf(2.0);
         f.sqrt()
     }
 
+    /// Unit vector pointing toward a compass bearing.
+    pub fn from_bearing(bearing: Degrees) -> Point {
+        let angle = bearing.to_radians();
+        Point {
+            x: angle.sin(),
+            y: angle.cos(),
+        }
+    }
+
+    /// Compass bearing of `other` as seen from this point, in `[0, 360)`.
+    pub fn bearing_to(&self, other: Point) -> Degrees {
+        Radians((other.x - self.x).atan2(other.y - self.y))
+            .to_degrees()
+            .wrap()
+    }
+
     // pub fn dot(&self, other: &Point) -> f64 {
     //     self.x * other.x + self.y * other.y
     // }
@@ -26,6 +54,87 @@ impl Point {
     // }
 }
 
+impl Degrees {
+    pub fn to_radians(self) -> Radians {
+        Radians(self.0.to_radians())
+    }
+
+    /// The same angle in `[0, 360)`.
+    pub fn wrap(self) -> Degrees {
+        let wrapped = self.0.rem_euclid(360.0);
+        // rem_euclid rounds tiny negative angles up to 360
+        Degrees(if wrapped >= 360.0 { 0.0 } else { wrapped })
+    }
+
+    /// The same angle in `(-180, 180]`.
+    pub fn wrap_signed(self) -> Degrees {
+        let wrapped = self.wrap().0;
+        Degrees(if wrapped > 180.0 {
+            wrapped - 360.0
+        } else {
+            wrapped
+        })
+    }
+
+    /// Signed turn from `other` to this angle, the shortest way around the
+    /// circle, in `(-180, 180]`. Positive turns are clockwise.
+    pub fn difference(self, other: Degrees) -> Degrees {
+        (self - other).wrap_signed()
+    }
+}
+
+impl Radians {
+    pub fn to_degrees(self) -> Degrees {
+        Degrees(self.0.to_degrees())
+    }
+
+    pub fn sin(self) -> f64 {
+        self.0.sin()
+    }
+
+    pub fn cos(self) -> f64 {
+        self.0.cos()
+    }
+}
+
+impl Add for Degrees {
+    type Output = Degrees;
+
+    fn add(self, other: Degrees) -> Degrees {
+        Degrees(self.0 + other.0)
+    }
+}
+
+impl Sub for Degrees {
+    type Output = Degrees;
+
+    fn sub(self, other: Degrees) -> Degrees {
+        Degrees(self.0 - other.0)
+    }
+}
+
+impl Neg for Degrees {
+    type Output = Degrees;
+
+    fn neg(self) -> Degrees {
+        Degrees(-self.0)
+    }
+}
+
+impl Mul<f64> for Degrees {
+    type Output = Degrees;
+
+    fn mul(self, scalar: f64) -> Degrees {
+        Degrees(self.0 * scalar)
+    }
+}
+
+impl fmt::Display for Degrees {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 impl Add for Point {
     type Output = Point;
 
@@ -97,3 +206,75 @@ impl fmt::Display for Point {
         write!(f, "({:.3}, {:.3})", self.x, self.y)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn from_bearing_points_clockwise_from_north() {
+        let north = Point::from_bearing(Degrees(0.0));
+        assert_close(north.x, 0.0);
+        assert_close(north.y, 1.0);
+
+        // A heading due east moves toward increasing x
+        let east = Point::from_bearing(Degrees(90.0));
+        assert_close(east.x, 1.0);
+        assert_close(east.y, 0.0);
+
+        let south_west = Point::from_bearing(Degrees(225.0));
+        assert_close(south_west.x, -0.5f64.sqrt());
+        assert_close(south_west.y, -0.5f64.sqrt());
+    }
+
+    #[test]
+    fn bearing_to_is_the_inverse_of_from_bearing() {
+        let origin = Point { x: 3.0, y: -2.0 };
+        assert_close(origin.bearing_to(origin + Point { x: 0.0, y: 5.0 }).0, 0.0);
+        assert_close(origin.bearing_to(origin + Point { x: 5.0, y: 0.0 }).0, 90.0);
+        assert_close(
+            origin.bearing_to(origin + Point { x: 0.0, y: -5.0 }).0,
+            180.0,
+        );
+        assert_close(
+            origin.bearing_to(origin + Point { x: -5.0, y: 0.0 }).0,
+            270.0,
+        );
+
+        for bearing in [10.0, 135.0, 200.0, 359.0] {
+            let target = origin + Point::from_bearing(Degrees(bearing)) * 7.0;
+            assert_close(origin.bearing_to(target).0, bearing);
+        }
+    }
+
+    #[test]
+    fn wrap_gives_angles_in_0_360() {
+        assert_close(Degrees(0.0).wrap().0, 0.0);
+        assert_close(Degrees(360.0).wrap().0, 0.0);
+        assert_close(Degrees(370.0).wrap().0, 10.0);
+        assert_close(Degrees(-90.0).wrap().0, 270.0);
+        assert_close(Degrees(-720.0).wrap().0, 0.0);
+        assert_close(Degrees(-1e-15).wrap().0, 0.0);
+    }
+
+    #[test]
+    fn difference_is_the_shortest_signed_turn() {
+        assert_close(Degrees(90.0).difference(Degrees(80.0)).0, 10.0);
+        assert_close(Degrees(80.0).difference(Degrees(90.0)).0, -10.0);
+        // Across north
+        assert_close(Degrees(10.0).difference(Degrees(350.0)).0, 20.0);
+        assert_close(Degrees(350.0).difference(Degrees(10.0)).0, -20.0);
+        // Half a turn is clockwise
+        assert_close(Degrees(180.0).difference(Degrees(0.0)).0, 180.0);
+        assert_close(Degrees(0.0).difference(Degrees(180.0)).0, 180.0);
+    }
+}
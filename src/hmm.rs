@@ -0,0 +1,402 @@
+use crate::config::RunConfig;
+use crate::distribution::SpeedDistribution;
+use crate::error::Error;
+use crate::gap::{nb_of_steps, GapConfig, GapPolicy};
+use crate::markov_graph::MarkovGraph;
+use crate::observation::{ContextPosteriors, Observation, StepEvent};
+use crate::particle::ParticleContextType;
+use crate::shore::ShorePenalty;
+use crate::utils::ln_sum_exp;
+
+use serde::{Deserialize, Serialize};
+
+/// Smallest standard deviation of the turn emission model, to keep its
+/// density finite when a heading jitter is 0
+const MIN_TURN_STD_DEV: f64 = 1e-3;
+
+/// Smallest speed the speed emission model is evaluated at, to keep the
+/// density of the log-normal and gamma distributions finite at a stopped
+/// vessel
+const MIN_SPEED: f64 = 1e-3;
+
+/// Emission terms of the hidden Markov model. The emission probability of an
+/// observation is the product of the enabled terms.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct HmmConfig {
+    /// Density of the observed speed under the speed distribution of the context
+    pub speed: bool,
+    /// Density of the observed turn under a centered normal distribution with
+    /// the variance of the heading change of the context. The heading jitter
+    /// should be fitted with `fit-motion` for this term to be meaningful
+    pub turn: bool,
+    /// Shore penalty of the context, see `shore_penalty`
+    pub shore: bool,
+}
+
+/// Hidden Markov model whose hidden states are the contexts of the Markov
+/// graph, and whose emissions are computed from the speed, turn and distance
+/// to the shore of the observations.
+#[derive(Debug)]
+pub struct Hmm {
+    states: Vec<ParticleContextType>,
+    ln_initial: Vec<f64>,
+    /// `ln_transition[i][j]` is the log probability of going from state `i`
+    /// to state `j`
+    ln_transition: Vec<Vec<f64>>,
+    emissions: Vec<EmissionModel>,
+    markov_graph: MarkovGraph<ParticleContextType>,
+    gap: GapConfig,
+    /// Time step of the transitions of the Markov graph
+    time_step: f64,
+}
+
+/// Emission model of one context
+#[derive(Debug, Clone, Copy)]
+struct EmissionModel {
+    speed: Option<SpeedDistribution>,
+    turn_std_dev: Option<f64>,
+    shore_penalty: Option<ShorePenalty>,
+}
+
+impl Default for HmmConfig {
+    fn default() -> Self {
+        HmmConfig {
+            speed: true,
+            turn: false,
+            shore: true,
+        }
+    }
+}
+
+impl Hmm {
+    /// Builds the model of a trajectory from the run configuration and its
+    /// Markov graph. The vessels start in the initial context of the set, or
+    /// in each context with its probability in `prior`.
+    pub fn new(
+        observations: &[Observation],
+        config: &RunConfig,
+        markov_graph: &MarkovGraph<ParticleContextType>,
+        prior: Option<&[(ParticleContextType, f64)]>,
+    ) -> Result<Hmm, Error> {
+        let contexts = config.context_set();
+        let states = contexts.order(&markov_graph.get_all_nodes());
+
+        // The vessels start in the first context of the set, unless the
+        // trajectory is a segment after a split gap
+        let initial_context = contexts.initial();
+        let ln_initial = if let Some(prior) = prior {
+            states
+                .iter()
+                .map(|state| {
+                    prior
+                        .iter()
+                        .find(|(context, _)| context == state)
+                        .map_or(f64::NEG_INFINITY, |(_, prob)| prob.ln())
+                })
+                .collect()
+        } else if states.contains(&initial_context) {
+            states
+                .iter()
+                .map(|state| {
+                    if *state == initial_context {
+                        0.0
+                    } else {
+                        f64::NEG_INFINITY
+                    }
+                })
+                .collect()
+        } else {
+            vec![-(states.len() as f64).ln(); states.len()]
+        };
+
+        let ln_transition = ln_transition_matrix(markov_graph, &states);
+
+        let emissions = states
+            .iter()
+            .map(|state| {
+                let context = contexts.lookup(state)?;
+                let (speed, turn_rms) = (context.speed, context.heading.turn_rms());
+                Ok(EmissionModel {
+                    speed: config.hmm.speed.then_some(speed),
+                    // Centered normal with the mean square heading change of
+                    // the context
+                    turn_std_dev: config.hmm.turn.then_some(turn_rms.max(MIN_TURN_STD_DEV)),
+                    shore_penalty: config
+                        .shore_penalty
+                        .get(state)
+                        .filter(|_| config.hmm.shore)
+                        .map(|penalty| penalty.resolve(observations)),
+                })
+            })
+            .collect::<Result<Vec<EmissionModel>, Error>>()?;
+
+        Ok(Hmm {
+            states,
+            ln_initial,
+            ln_transition,
+            emissions,
+            markov_graph: markov_graph.clone(),
+            gap: config.gap,
+            time_step: config.gap.resolve_time_step(observations),
+        })
+    }
+
+    /// Log transition matrix into the observation when it comes after a gap
+    /// handled by reinitializing: the transitions of the graph over the
+    /// duration of the gap. `None` for the other observations.
+    fn gap_ln_transition(&self, observation: &Observation) -> Option<Vec<Vec<f64>>> {
+        if self.gap.policy != GapPolicy::Reinitialize || !self.gap.is_gap(observation) {
+            return None;
+        }
+        let graph = self
+            .markov_graph
+            .power(nb_of_steps(observation.time, self.time_step));
+        Some(ln_transition_matrix(&graph, &self.states))
+    }
+
+    /// Gaps crossed by the decoder at each observation.
+    pub fn events(&self, observations: &[Observation]) -> Vec<StepEvent> {
+        observations
+            .iter()
+            .enumerate()
+            .map(|(i, observation)| {
+                if i > 0 && self.gap_ln_transition(observation).is_some() {
+                    StepEvent::Gap(GapPolicy::Reinitialize)
+                } else {
+                    StepEvent::None
+                }
+            })
+            .collect()
+    }
+
+    /// Log probability of each state emitting the observation.
+    fn ln_emissions(&self, observation: &Observation) -> Vec<f64> {
+        self.emissions
+            .iter()
+            .map(|emission| emission.ln_prob(observation))
+            .collect()
+    }
+
+    /// Most likely sequence of contexts given the observations, computed with
+    /// the Viterbi algorithm in log space. Fails if no sequence of contexts
+    /// can explain the observations.
+    pub fn viterbi(&self, observations: &[Observation]) -> Result<Vec<ParticleContextType>, Error> {
+        let nb_of_states = self.states.len();
+        if observations.is_empty() || nb_of_states == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut backpointers: Vec<Vec<usize>> = Vec::with_capacity(observations.len());
+        let mut scores: Vec<f64> = self
+            .ln_initial
+            .iter()
+            .zip(self.ln_emissions(&observations[0]))
+            .map(|(ln_initial, ln_emission)| ln_initial + ln_emission)
+            .collect();
+
+        for observation in &observations[1..] {
+            let gap_ln_transition = self.gap_ln_transition(observation);
+            let ln_transition = gap_ln_transition.as_ref().unwrap_or(&self.ln_transition);
+            let ln_emissions = self.ln_emissions(observation);
+            let mut new_scores = vec![f64::NEG_INFINITY; nb_of_states];
+            let mut pointers = vec![0; nb_of_states];
+
+            for (j, ln_emission) in ln_emissions.iter().enumerate() {
+                let (best_i, best_score) = scores
+                    .iter()
+                    .enumerate()
+                    .map(|(i, score)| (i, score + ln_transition[i][j]))
+                    .fold((0, f64::NEG_INFINITY), |best, candidate| {
+                        if candidate.1 > best.1 {
+                            candidate
+                        } else {
+                            best
+                        }
+                    });
+                new_scores[j] = best_score + ln_emission;
+                pointers[j] = best_i;
+            }
+
+            scores = new_scores;
+            backpointers.push(pointers);
+        }
+
+        // Backtrack from the best final state
+        let mut state = argmax(&scores);
+        if !scores[state].is_finite() {
+            return Err(infeasible("the most likely contexts"));
+        }
+        let mut path = vec![self.states[state].clone()];
+        for pointers in backpointers.iter().rev() {
+            state = pointers[state];
+            path.push(self.states[state].clone());
+        }
+        path.reverse();
+
+        Ok(path)
+    }
+
+    /// Posterior probability of each state at every observation given all
+    /// the observations, computed with the forward-backward algorithm in log
+    /// space. Fails if no sequence of contexts can explain the observations.
+    pub fn forward_backward(
+        &self,
+        observations: &[Observation],
+    ) -> Result<ContextPosteriors, Error> {
+        let nb_of_states = self.states.len();
+        let mut posteriors = ContextPosteriors {
+            contexts: self.states.clone(),
+            probs: Vec::with_capacity(observations.len()),
+        };
+        if observations.is_empty() || nb_of_states == 0 {
+            return Ok(posteriors);
+        }
+
+        let ln_emissions: Vec<Vec<f64>> = observations
+            .iter()
+            .map(|observation| self.ln_emissions(observation))
+            .collect();
+
+        // ln_alpha[t][j] = ln P(o_0..o_t, s_t = j)
+        let mut ln_alpha: Vec<Vec<f64>> = Vec::with_capacity(observations.len());
+        ln_alpha.push(
+            self.ln_initial
+                .iter()
+                .zip(&ln_emissions[0])
+                .map(|(ln_initial, ln_emission)| ln_initial + ln_emission)
+                .collect(),
+        );
+        let ln_transitions: Vec<Option<Vec<Vec<f64>>>> = observations
+            .iter()
+            .map(|observation| self.gap_ln_transition(observation))
+            .collect();
+        let ln_transition = |t: usize| ln_transitions[t].as_ref().unwrap_or(&self.ln_transition);
+
+        for (t, ln_emission) in ln_emissions.iter().enumerate().skip(1) {
+            let previous = &ln_alpha[t - 1];
+            let alpha = (0..nb_of_states)
+                .map(|j| {
+                    ln_sum_exp((0..nb_of_states).map(|i| previous[i] + ln_transition(t)[i][j]))
+                        + ln_emission[j]
+                })
+                .collect();
+            ln_alpha.push(alpha);
+        }
+
+        // ln_beta[t][i] = ln P(o_t+1..o_T | s_t = i)
+        let mut ln_beta = vec![vec![0.0; nb_of_states]; observations.len()];
+        for t in (0..observations.len() - 1).rev() {
+            for i in 0..nb_of_states {
+                ln_beta[t][i] = ln_sum_exp((0..nb_of_states).map(|j| {
+                    ln_transition(t + 1)[i][j] + ln_emissions[t + 1][j] + ln_beta[t + 1][j]
+                }));
+            }
+        }
+
+        for (alpha, beta) in ln_alpha.iter().zip(&ln_beta) {
+            let ln_joint: Vec<f64> = alpha.iter().zip(beta).map(|(a, b)| a + b).collect();
+            let ln_total = ln_sum_exp(ln_joint.iter().copied());
+            if !ln_total.is_finite() {
+                return Err(infeasible("the context posteriors"));
+            }
+            posteriors.probs.push(
+                ln_joint
+                    .iter()
+                    .map(|ln_p| (ln_p - ln_total).exp())
+                    .collect(),
+            );
+        }
+
+        Ok(posteriors)
+    }
+}
+
+/// `ln_transition[i][j]` is the log probability of going from `states[i]` to
+/// `states[j]`.
+fn ln_transition_matrix(
+    markov_graph: &MarkovGraph<ParticleContextType>,
+    states: &[ParticleContextType],
+) -> Vec<Vec<f64>> {
+    states
+        .iter()
+        .map(|src| {
+            states
+                .iter()
+                .map(|dest| markov_graph.transition_prob(src, dest).ln())
+                .collect()
+        })
+        .collect()
+}
+
+impl EmissionModel {
+    fn ln_prob(&self, observation: &Observation) -> f64 {
+        let mut ln_prob = 0.0;
+
+        if let Some(speed) = self.speed {
+            if observation.speed.is_finite() {
+                ln_prob += speed.ln_pdf(observation.speed.max(MIN_SPEED));
+            }
+        }
+        if let Some(std_dev) = self.turn_std_dev {
+            if observation.turn.is_finite() {
+                let ln_sqrt_two_pi = 0.5 * (2.0 * std::f64::consts::PI).ln();
+                ln_prob +=
+                    -ln_sqrt_two_pi - std_dev.ln() - 0.5 * (observation.turn / std_dev).powi(2);
+            }
+        }
+        if let Some(penalty) = self.shore_penalty {
+            ln_prob += penalty.weight_scale(observation.distance_to_shore).ln();
+        }
+
+        ln_prob
+    }
+}
+
+/// Index of the largest value, the first one in case of a tie.
+pub(crate) fn argmax(values: &[f64]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .fold((0, f64::NEG_INFINITY), |best, (i, &value)| {
+            if value > best.1 {
+                (i, value)
+            } else {
+                best
+            }
+        })
+        .0
+}
+
+/// Error of a decoder when no sequence of contexts can explain the
+/// observations, e.g. when every context forbids an observed speed.
+fn infeasible(what: &str) -> Error {
+    Error::Numerical(format!(
+        "{} cannot be computed: no sequence of contexts can explain the observations",
+        what
+    ))
+}
+
+/// Most probable context of each observation according to the posteriors.
+pub fn posterior_path(posteriors: &ContextPosteriors) -> Vec<ParticleContextType> {
+    posteriors
+        .probs
+        .iter()
+        .map(|probs| posteriors.contexts[argmax(probs)].clone())
+        .collect()
+}
+
+/// Labels the observations with the contexts of a decoded path.
+pub fn label_observations(
+    observations: &[Observation],
+    path: &[ParticleContextType],
+) -> Vec<Observation> {
+    observations
+        .iter()
+        .zip(path)
+        .map(|(observation, context)| Observation {
+            context: context.clone(),
+            ..observation.clone()
+        })
+        .collect()
+}
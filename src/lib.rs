@@ -0,0 +1,73 @@
+//! Context matching for fishing trajectories: labels each AIS observation of
+//! a vessel with its context, e.g. going fishing, fishing or going back to
+//! port, with a particle filter or a hidden Markov model.
+//!
+//! A run is described by a [`RunConfig`]: the context states and their motion
+//! models, the Markov graph of the transitions between contexts, the decoder
+//! and its parameters. Observations are built from the records of an AIS file
+//! or from records in memory, then [`decode`] labels them and gives the
+//! probability of each context at every observation. Failures, e.g. an
+//! unreadable graph file, are reported as an [`Error`]:
+//!
+//! ```no_run
+//! use context_matching::{decode, AisRecord, Error, Observation, RunConfig};
+//!
+//! # fn records() -> Vec<AisRecord> { Vec::new() }
+//! let config = RunConfig::default();
+//! config.validate()?;
+//!
+//! let records: Vec<AisRecord> = records();
+//! let observations = Observation::from_records(&records, &config.context_set());
+//! let decoding = decode(&observations, &config)?;
+//!
+//! for (state, probs) in decoding.states.iter().zip(&decoding.posteriors.probs) {
+//!     println!("{} {:?}", state.context, probs);
+//! }
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! [`decode`] reads the graph and port files of the configuration at every
+//! call. To decode many trajectories, they are read once with [`RunFiles`]
+//! and the trajectories decoded with [`decode_with_files`].
+//!
+//! The types of every section of a run configuration are in [`config`].
+//! Besides decoding, the crate evaluates results against labels
+//! ([`evaluate`]), learns Markov graphs and motion models from labelled
+//! trajectories ([`training`]), simulates labelled trajectories
+//! ([`simulate`]) and processes many trajectory files in parallel
+//! ([`batch`]). The `context-matching` binary is a command line interface
+//! over this crate.
+
+pub mod batch;
+pub mod config;
+pub mod decoding;
+pub mod error;
+pub mod evaluate;
+pub mod markov_graph;
+pub mod observation;
+pub mod segment;
+pub mod simulate;
+pub mod training;
+
+mod collapse;
+mod context;
+mod distribution;
+mod fishing_context;
+mod gap;
+mod gear;
+mod geometry;
+mod hmm;
+mod lineage;
+mod motion;
+mod particle;
+mod port;
+mod random_generator;
+mod resampling;
+mod shore;
+mod utils;
+
+pub use config::{Decoder, RunConfig, RunFiles};
+pub use decoding::{decode, decode_with_files, Decoding};
+pub use error::Error;
+pub use observation::{AisRecord, ContextPosteriors, Observation, ParticleContextType, StepEvent};
+pub use random_generator::{derive_seed, random_seed};
@@ -0,0 +1,53 @@
+use crate::particle::ParticleContextType;
+
+/// Shared genealogy of the particles. Every step of every particle is a node
+/// of an arena pointing to the node of its parent at the previous step, so
+/// that resampling a particle only copies the index of its last node instead
+/// of its whole context history. Memory and time grow linearly with the
+/// length of the trajectory.
+#[derive(Debug, Default)]
+pub struct Lineage {
+    nodes: Vec<LineageNode>,
+}
+
+#[derive(Debug, Clone)]
+struct LineageNode {
+    parent: Option<usize>,
+    context: ParticleContextType,
+}
+
+impl Lineage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a new lineage with its first context and returns its node.
+    pub fn root(&mut self, context: ParticleContextType) -> usize {
+        self.push(None, context)
+    }
+
+    /// Extends the lineage ending at `parent` with the context of the next
+    /// step and returns the new node.
+    pub fn extend(&mut self, parent: usize, context: ParticleContextType) -> usize {
+        self.push(Some(parent), context)
+    }
+
+    fn push(&mut self, parent: Option<usize>, context: ParticleContextType) -> usize {
+        self.nodes.push(LineageNode { parent, context });
+        self.nodes.len() - 1
+    }
+
+    /// Contexts of the lineage ending at `node`, from its root to `node`.
+    pub fn path(&self, node: usize) -> Vec<ParticleContextType> {
+        let mut path = Vec::new();
+        let mut current = Some(node);
+        while let Some(index) = current {
+            let node = &self.nodes[index];
+            path.push(node.context.clone());
+            current = node.parent;
+        }
+        path.reverse();
+
+        path
+    }
+}
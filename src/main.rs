@@ -1,52 +1,336 @@
-#![allow(dead_code, unused_imports, unused_mut, unused_variables)]
-mod fishing_context;
-mod geometry;
-mod markov_graph;
-mod observation;
-mod particle;
-mod random_generator;
-mod utils;
-
-use fishing_context::FishingContext;
-use observation::Observation;
-use std::env;
-use std::error;
-use std::time::Instant;
-
-fn main() -> Result<(), Box<dyn error::Error>> {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() < 3 || args.len() > 4 {
-        return Err(
-            "Bad number of arguments: <input_csv_file_path> <output_result_path> <history_path>"
-                .into(),
-        );
+mod cli;
+
+use clap::Parser;
+use cli::{
+    BatchArgs, Cli, Command, EvaluateArgs, FilterArgs, FitMotionArgs, InspectGraphArgs, RunArgs,
+    SimulateArgs, TrainGraphArgs,
+};
+use context_matching::batch::{self, BatchProgress};
+use context_matching::config::{
+    ContextConfig, ContextSet, Decoder, RunConfig, RunFiles, VesselRegistry,
+};
+use context_matching::decoding::{
+    decode_with_files, vessel_file_path, write_results, write_vessel_results, Decoding,
+};
+use context_matching::error::Error;
+use context_matching::evaluate::{EvaluationReport, LabelledTrajectory};
+use context_matching::markov_graph::{read_graph_from_file, write_graph_to_file, MarkovGraph};
+use context_matching::observation::{AisRecord, Observation, ParticleContextType};
+use context_matching::segment::{write_segments, Segment};
+use context_matching::simulate::{SimulationConfig, Simulator};
+use context_matching::training::{fit_speed, write_dwell_stats, MotionSamples, TransitionStats};
+use context_matching::{derive_seed, random_seed};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+/// Exit code used when the configuration or the arguments are invalid
+const EXIT_INVALID_CONFIG: u8 = 2;
+
+/// Summary of the particle filtering of one trajectory
+struct FilterReport {
+    correct_context: usize,
+    false_context: usize,
+    duration: Duration,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Run(args) => match load_config(&args.filter, args.history.as_deref()) {
+            Ok(config) => run(&args, config),
+            Err(e) => return invalid_config(e),
+        },
+        Command::Batch(args) => match load_config(&args.filter, None) {
+            Ok(config) => batch(&args, &config),
+            Err(e) => return invalid_config(e),
+        },
+        Command::Evaluate(args) => evaluate(&args),
+        Command::InspectGraph(args) => inspect_graph(&args),
+        Command::TrainGraph(args) => train_graph(&args),
+        Command::FitMotion(args) => fit_motion(&args),
+        Command::Simulate(args) => simulate(&args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e @ Error::Config(_)) => invalid_config(e),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run configuration of the flags, with the history file of `run`. It is
+/// validated once here, before decoding any trajectory.
+fn load_config(args: &FilterArgs, history: Option<&str>) -> Result<RunConfig, Error> {
+    let mut config = args.to_config()?;
+    if let Some(history) = history {
+        config.history.path = Some(history.to_string());
+    }
+    config.validate()?;
+    // Always record the seed so that the run can be reproduced
+    config.seed.get_or_insert_with(random_seed);
+    Ok(config)
+}
+
+/// Context states of a run configuration file, or the default ones.
+fn load_context_set(path: Option<&str>) -> Result<ContextSet, Error> {
+    let config = match path {
+        Some(path) => RunConfig::from_file(path)?,
+        None => RunConfig::default(),
+    };
+    let contexts = config.context_set();
+    contexts.validate()?;
+    Ok(contexts)
+}
+
+fn invalid_config(e: Error) -> ExitCode {
+    eprintln!("error: {}", e);
+    ExitCode::from(EXIT_INVALID_CONFIG)
+}
+
+fn run(args: &RunArgs, config: RunConfig) -> Result<(), Error> {
+    if args.by_vessel {
+        return run_by_vessel(args, &config);
+    }
+
+    match config.decoder {
+        Decoder::ParticleFilter => println!("\nParticle filtering {}...", args.input),
+        Decoder::Viterbi => println!("\nViterbi decoding {}...", args.input),
+        Decoder::ForwardBackward => println!("\nForward-backward decoding {}...", args.input),
+    }
+    let registry = config.gear.load_registry()?;
+    let files = RunFiles::read(&config)?;
+    let report = process_file(&args.input, &args.output, &config, &registry, &files)?;
+    println!("{}", report);
+    println!("Results were written to {}.", args.output);
+
+    Ok(())
+}
+
+fn run_by_vessel(args: &RunArgs, config: &RunConfig) -> Result<(), Error> {
+    println!("\nReading and parsing input CSV file {}...", args.input);
+    let registry = config.gear.load_registry()?;
+    let files = RunFiles::read(config)?;
+    let vessels = AisRecord::from_csv_by_vessel(&args.input)?;
+    if vessels.is_empty() {
+        return Err(Error::Input(format!(
+            "input file {} has no observation",
+            args.input
+        )));
+    }
+    println!("Found {} vessels.", vessels.len());
+
+    if args.split_output {
+        fs::create_dir_all(&args.output).map_err(|e| Error::io(&args.output, e))?;
     }
 
-    println!("\nReading and parsing input CSV file...");
-    let observations = Observation::from_csv(&args[1])?;
+    let mut results: Vec<(String, Decoding)> = Vec::new();
+    let mut segments: Vec<Segment> = Vec::new();
+    for (id, records) in vessels {
+        let (gear, mut vessel_config) = config.for_vessel(&id, &records, &registry)?;
+        match gear {
+            Some(gear) => println!("\nVessel {} ({}):", id, gear),
+            None => println!("\nVessel {}:", id),
+        }
+        let observations = Observation::from_records(&records, &vessel_config.context_set());
+        vessel_config.history.path = config
+            .history
+            .path
+            .as_ref()
+            .map(|path| vessel_file_path(path, &id));
+        vessel_config.seed = config.seed.map(|seed| derive_seed(seed, &id));
+
+        let (decoding, report) = filter_observations(&observations, &vessel_config, &files)?;
+        println!("{}", report);
 
-    println!("Particle filtering...");
-    let mut history_file: Option<String> = None;
-    if args.len() == 4 {
-        history_file = Some(args[3].clone());
+        if args.split_output {
+            let output = Path::new(&args.output).join(format!("{}.csv", id));
+            let output = output.to_string_lossy();
+            write_results(&output, &decoding)?;
+            if config.output.echo_config {
+                vessel_config.echo_to(&output)?;
+            }
+            if let Some(format) = config.output.segments {
+                write_segments(&output, &decoding.segments(&id, &records), format)?;
+            }
+        } else {
+            if config.output.segments.is_some() {
+                segments.extend(decoding.segments(&id, &records));
+            }
+            results.push((id, decoding));
+        }
     }
 
+    if !args.split_output {
+        println!("\nWriting results to output file...");
+        write_vessel_results(&args.output, &results)?;
+        if config.output.echo_config {
+            config.echo_to(&args.output)?;
+        }
+        if let Some(format) = config.output.segments {
+            let path = write_segments(&args.output, &segments, format)?;
+            println!("Segments were written to {}.", path);
+        }
+    }
+    println!("Results were written to {}.", args.output);
+
+    Ok(())
+}
+
+fn batch(args: &BatchArgs, config: &RunConfig) -> Result<(), Error> {
+    let input_files = batch::collect_input_files(&args.input)?;
+    fs::create_dir_all(&args.output_dir).map_err(|e| Error::io(&args.output_dir, e))?;
+    let registry = config.gear.load_registry()?;
+    let files = RunFiles::read(config)?;
+
     let start = Instant::now();
-    let mut ctx = FishingContext::new(
-        observations.as_slice(),
-        100,
-        5.0,
-        (3.31, 1.19),
-        (1.36, 0.89),
-        51,
-        history_file,
+    let failures = batch::run_batch(
+        &input_files,
+        args.threads,
+        |input_file| {
+            let relative_path = batch::relative_path(&args.input, input_file);
+            let output_file = Path::new(&args.output_dir).join(&relative_path);
+            if let Some(output_subdir) = output_file.parent() {
+                fs::create_dir_all(output_subdir)
+                    .map_err(|e| Error::io(&output_subdir.to_string_lossy(), e))?;
+            }
+            let mut file_config = config.clone();
+            file_config.seed = config
+                .seed
+                .map(|seed| derive_seed(seed, &relative_path.to_string_lossy()));
+            process_file(
+                &input_file.to_string_lossy(),
+                &output_file.to_string_lossy(),
+                &file_config,
+                &registry,
+                &files,
+            )
+            .map(|report| report.to_string())
+        },
+        print_batch_progress,
+    )?;
+
+    println!(
+        "\nProcessed {} files in {:?}: {} succeeded, {} failed.",
+        input_files.len(),
+        start.elapsed(),
+        input_files.len() - failures.len(),
+        failures.len()
     );
-    let states: Vec<Observation> = ctx.particle_filter();
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    println!("Failed files:");
+    for failure in &failures {
+        println!("  {}: {}", failure.input.display(), failure.reason);
+    }
+
+    Err(Error::Input(format!(
+        "{} of {} files failed",
+        failures.len(),
+        input_files.len()
+    )))
+}
+
+fn print_batch_progress(progress: &BatchProgress) {
+    match progress {
+        BatchProgress::Started {
+            nb_of_files,
+            nb_of_threads,
+        } => println!(
+            "Processing {} files on {} threads...",
+            nb_of_files, nb_of_threads
+        ),
+        BatchProgress::FileDone {
+            done,
+            nb_of_files,
+            input,
+            result: Ok(summary),
+        } => println!(
+            "[{}/{}] {}: {}",
+            done,
+            nb_of_files,
+            input.display(),
+            summary
+        ),
+        BatchProgress::FileDone {
+            done,
+            nb_of_files,
+            input,
+            result: Err(reason),
+        } => println!(
+            "[{}/{}] {}: FAILED: {}",
+            done,
+            nb_of_files,
+            input.display(),
+            reason
+        ),
+    }
+}
+
+/// Filters one trajectory file and writes its result file.
+fn process_file(
+    input: &str,
+    output: &str,
+    config: &RunConfig,
+    registry: &VesselRegistry,
+    files: &RunFiles,
+) -> Result<FilterReport, Error> {
+    let records = AisRecord::from_csv(input)?;
+    if records.is_empty() {
+        return Err(Error::Input(format!(
+            "input file {} has no observation",
+            input
+        )));
+    }
+    // A trajectory file holds a single vessel
+    let (_, vessel_config) = config.for_vessel(&records[0].id, &records, registry)?;
+    let observations = Observation::from_records(&records, &vessel_config.context_set());
+
+    let (decoding, report) = filter_observations(&observations, &vessel_config, files)?;
+
+    write_results(output, &decoding)?;
+
+    if vessel_config.output.echo_config {
+        vessel_config.echo_to(output)?;
+    }
+    if let Some(format) = vessel_config.output.segments {
+        write_segments(output, &decoding.segments(&records[0].id, &records), format)?;
+    }
+
+    Ok(report)
+}
+
+/// Decodes the observations and reports the time it took and the number of
+/// states matching the labels.
+fn filter_observations(
+    observations: &[Observation],
+    config: &RunConfig,
+    files: &RunFiles,
+) -> Result<(Decoding, FilterReport), Error> {
+    let start = Instant::now();
+    let decoding = decode_with_files(observations, config, files)?;
     let duration = start.elapsed();
-    println!("Particle filtering took {:?}", duration);
 
-    println!("\nAnalyzing results...");
+    let (correct_context, false_context) = count_contexts(&decoding.states, observations);
+    let report = FilterReport {
+        correct_context,
+        false_context,
+        duration,
+    };
+
+    Ok((decoding, report))
+}
+
+/// Counts the states whose context matches the label of the observation.
+fn count_contexts(states: &[Observation], observations: &[Observation]) -> (usize, usize) {
     let (mut correct_context, mut false_context) = (0, 0);
     for (state, observation) in states.iter().zip(observations.iter()) {
         if state.context == observation.context {
@@ -55,32 +339,300 @@ fn main() -> Result<(), Box<dyn error::Error>> {
             false_context += 1;
         }
     }
+    (correct_context, false_context)
+}
+
+fn evaluate(args: &EvaluateArgs) -> Result<(), Error> {
+    let contexts = load_context_set(args.config.as_deref())?;
+    let result_files = batch::collect_input_files(&args.result)?;
+    let input_dir = Path::new(&args.input).is_dir();
+    if !input_dir && result_files.len() > 1 {
+        return Err(Error::Config(format!(
+            "input {} must be a directory to evaluate {} result files",
+            args.input,
+            result_files.len()
+        )));
+    }
+
+    let mut trajectories = Vec::new();
+    for result_file in &result_files {
+        let file_name = result_file.file_name().unwrap_or_default();
+        let input_file = if input_dir {
+            Path::new(&args.input).join(file_name)
+        } else {
+            Path::new(&args.input).to_path_buf()
+        };
+        let (input_file, result_file) =
+            (input_file.to_string_lossy(), result_file.to_string_lossy());
+
+        // Each vessel is a trajectory, its input records sorted by time as
+        // in the `--by-vessel` runs
+        let vessels = Observation::from_csv_by_vessel(&input_file, &contexts)?;
+        let results = Observation::from_result_csv_by_vessel(&result_file)?;
+        if results.len() != vessels.len() {
+            return Err(Error::Input(format!(
+                "result file {} has {} vessels but input file {} has {}",
+                result_file,
+                results.len(),
+                input_file,
+                vessels.len()
+            )));
+        }
+
+        for (id, states) in &results {
+            let (name, observations) = match id {
+                Some(id) => {
+                    let (_, observations) = vessels
+                        .iter()
+                        .find(|(vessel_id, _)| vessel_id == id)
+                        .ok_or_else(|| {
+                        Error::Input(format!(
+                            "vessel {} of result file {} is not in input file {}",
+                            id, result_file, input_file
+                        ))
+                    })?;
+                    (
+                        format!("{} {}", file_name.to_string_lossy(), id),
+                        observations,
+                    )
+                }
+                // A result file without ids holds the single vessel of its
+                // input file
+                None => (file_name.to_string_lossy().into_owned(), &vessels[0].1),
+            };
+            if states.len() != observations.len() {
+                return Err(Error::Input(format!(
+                    "{} has {} rows in result file {} but {} observations in input file {}",
+                    name,
+                    states.len(),
+                    result_file,
+                    observations.len(),
+                    input_file
+                )));
+            }
+
+            trajectories.push(LabelledTrajectory {
+                name,
+                truth: observations.iter().map(|obs| obs.context.clone()).collect(),
+                predicted: states.iter().map(|state| state.context.clone()).collect(),
+            });
+        }
+    }
+
+    let report = EvaluationReport::new(&trajectories, contexts.names());
+    println!("{}", report);
+
+    if let Some(report_file) = &args.report {
+        let content =
+            serde_json::to_string_pretty(&report).map_err(|e| Error::io(report_file, e.into()))?;
+        fs::write(report_file, content).map_err(|e| Error::io(report_file, e))?;
+        println!("\nReport was written to {}.", report_file);
+    }
+
+    Ok(())
+}
+
+fn inspect_graph(args: &InspectGraphArgs) -> Result<(), Error> {
+    if !Path::new(&args.graph).is_file() {
+        return Err(Error::Input(format!(
+            "graph file {} does not exist",
+            args.graph
+        )));
+    }
+    let markov_graph: MarkovGraph<ParticleContextType> = read_graph_from_file(&args.graph)?;
+    println!("{}", markov_graph);
+    markov_graph
+        .validate()
+        .map_err(|e| Error::Input(format!("invalid graph file {}: {}", args.graph, e)))?;
+    println!("The graph is a valid transition matrix.");
+
+    Ok(())
+}
+
+fn train_graph(args: &TrainGraphArgs) -> Result<(), Error> {
+    if args
+        .time_step
+        .is_some_and(|time_step| !(time_step.is_finite() && time_step > 0.0))
+    {
+        return Err(Error::Config(String::from(
+            "--time-step must be a positive number",
+        )));
+    }
+
+    let contexts = load_context_set(args.config.as_deref())?;
+    let input_files = batch::collect_input_files(&args.input)?;
+
+    let mut stats = TransitionStats::new();
+    let mut nb_of_trajectories = 0;
+    for input_file in &input_files {
+        let input_file = input_file.to_string_lossy();
+        let vessels = Observation::from_csv_by_vessel(&input_file, &contexts)?;
+        for (_, observations) in vessels {
+            stats.add_trajectory(&observations);
+            nb_of_trajectories += 1;
+        }
+    }
     println!(
-        "Context --> correct: {}, false: {}. Success rate: {}",
-        correct_context,
-        false_context,
-        correct_context as f32 / (correct_context + false_context) as f32
+        "Counted context transitions of {} trajectories in {} files.",
+        nb_of_trajectories,
+        input_files.len()
     );
 
-    println!("\nWriting results to output file...");
-    let mut wtr = csv::Writer::from_path(&args[2])?;
+    let markov_graph = stats.to_graph(args.time_step);
+    println!("\n{}", markov_graph);
+    write_graph_to_file(&markov_graph, &args.output)?;
+    println!("Markov graph was written to {}.", args.output);
+
+    let dwell_stats = stats.dwell_stats();
+    println!("\nDwell time per context:");
+    for (context, stat) in &dwell_stats {
+        println!("  {}: {}", context, stat);
+    }
+    if let Some(dwell_output) = &args.dwell_output {
+        write_dwell_stats(dwell_output, &dwell_stats)?;
+        println!("Dwell time statistics were written to {}.", dwell_output);
+    }
+
+    Ok(())
+}
+
+fn fit_motion(args: &FitMotionArgs) -> Result<(), Error> {
+    let mut config = match &args.config {
+        Some(path) => RunConfig::from_file(path)?,
+        None => RunConfig::default(),
+    };
+
+    // Contexts of the gear profile to fit, or of the run
+    let profile = match &args.profile {
+        Some(name) => Some(
+            config
+                .profile(name)
+                .ok_or_else(|| Error::Config(format!("unknown gear profile {}", name)))?,
+        ),
+        None => None,
+    };
+    let contexts = match &profile {
+        Some(profile) if !profile.contexts.is_empty() => ContextSet::new(profile.contexts.clone()),
+        _ => config.context_set(),
+    };
+    contexts.validate()?;
 
-    wtr.write_record(&["x", "y", "time", "heading", "speed", "context"])?;
+    let input_files = batch::collect_input_files(&args.input)?;
 
-    for state in states {
-        wtr.serialize((
-            state.pos.x,
-            state.pos.y,
-            state.time,
-            state.heading,
-            state.speed,
-            state.context,
-        ))?;
+    let mut samples = MotionSamples::new();
+    for input_file in &input_files {
+        let input_file = input_file.to_string_lossy();
+        let vessels = Observation::from_csv_by_vessel(&input_file, &contexts)?;
+        for (_, observations) in vessels {
+            samples.add_trajectory(&observations);
+        }
     }
 
-    wtr.flush()?;
+    let mut fitted_contexts = Vec::new();
+    for context in contexts.iter() {
+        let speeds = samples.speeds(&context.name);
+        let speed_fit = fit_speed(speeds, args.family).ok_or_else(|| {
+            Error::Input(format!(
+                "not enough {} observations to fit a speed distribution",
+                context.name
+            ))
+        })?;
+        println!(
+            "{} speed: {} (mean log-likelihood {:.3}, {} samples)",
+            context.name,
+            speed_fit.distribution,
+            speed_fit.mean_ln_likelihood,
+            speeds.len()
+        );
+
+        let heading = match samples.fit_heading(&context.name, context.heading) {
+            Some(heading) => {
+                println!("{} heading: {}", context.name, heading);
+                heading
+            }
+            None => {
+                println!(
+                    "Not enough {} turns to fit the heading jitter, keeping the current one.",
+                    context.name
+                );
+                context.heading
+            }
+        };
 
-    println!("Results were written to the file.");
+        fitted_contexts.push(ContextConfig {
+            speed: speed_fit.distribution,
+            heading,
+            ..context.clone()
+        });
+    }
+    match (&args.profile, profile) {
+        (Some(name), Some(mut profile)) => {
+            profile.contexts = fitted_contexts;
+            config.profiles.insert(name.clone(), profile);
+        }
+        _ => config.contexts = fitted_contexts,
+    }
+
+    config.to_file(&args.output)?;
+    println!("Run configuration was written to {}.", args.output);
 
     Ok(())
 }
+
+fn simulate(args: &SimulateArgs) -> Result<(), Error> {
+    let mut config = match &args.config {
+        Some(path) => RunConfig::from_file(path)?,
+        None => RunConfig::default(),
+    };
+    if let Some(gear) = &args.gear {
+        config = config.with_profile(gear)?;
+    }
+    config.validate()?;
+
+    let simulation = SimulationConfig {
+        nb_of_trajectories: args.trajectories,
+        nb_of_observations: args.observations,
+        interval: args.interval,
+        position_noise: args.position_noise,
+        gap_probability: args.gap_probability,
+        gap_duration: args.gap_duration,
+        port: args.port,
+        gear: args.gear.clone(),
+        seed: args.seed.unwrap_or_else(random_seed),
+    };
+    simulation.validate()?;
+    println!("Simulating with seed {}.", simulation.seed);
+
+    let markov_graph: MarkovGraph<ParticleContextType> = read_graph_from_file(&config.graph_path)?;
+    let simulator = Simulator::new(simulation, config.context_set(), markov_graph);
+
+    if args.split_output {
+        fs::create_dir_all(&args.output).map_err(|e| Error::io(&args.output, e))?;
+        for id in simulator.ids() {
+            let output = Path::new(&args.output).join(format!("{}.csv", id));
+            AisRecord::to_csv(&output.to_string_lossy(), &simulator.trajectory(&id)?)?;
+        }
+    } else {
+        let mut records: Vec<AisRecord> = Vec::new();
+        for id in simulator.ids() {
+            records.extend(simulator.trajectory(&id)?);
+        }
+        AisRecord::to_csv(&args.output, &records)?;
+    }
+    println!("Trajectories were written to {}.", args.output);
+
+    Ok(())
+}
+
+impl fmt::Display for FilterReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Context --> correct: {}, false: {}. Success rate: {} (took {:?})",
+            self.correct_context,
+            self.false_context,
+            self.correct_context as f32 / (self.correct_context + self.false_context) as f32,
+            self.duration
+        )
+    }
+}
@@ -1,7 +1,13 @@
-use std::collections::HashMap;
+use crate::error::Error;
+use crate::random_generator::random_uniform_range;
+use rand::Rng;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+/// Maximum difference between one and the sum of the outgoing weights of a node
+const ROW_SUM_TOLERANCE: f64 = 1e-6;
 
 #[derive(Clone, Debug)]
 struct Edge<N> {
@@ -9,37 +15,152 @@ struct Edge<N> {
     weight: f64,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MarkovGraph<N> {
-    adj_list: HashMap<N, Vec<Edge<N>>>,
+    adj_list: BTreeMap<N, Vec<Edge<N>>>,
+}
+
+impl<N> Default for MarkovGraph<N>
+where
+    N: Clone + Ord,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<N> MarkovGraph<N>
 where
-    N: Clone + Eq + PartialEq + std::hash::Hash,
+    N: Clone + Ord,
 {
     pub fn new() -> Self {
         Self {
-            adj_list: HashMap::new(),
+            adj_list: BTreeMap::new(),
         }
     }
 
     pub fn add_edge(&mut self, src: N, dest: N, weight: f64) {
         self.adj_list
             .entry(src)
-            .or_insert(Vec::new())
+            .or_default()
             .push(Edge { dest, weight });
     }
 
-    pub fn get_dest(&self, src: N, weight: f64) -> Option<N> {
-        if let Some(edges) = self.adj_list.get(&src) {
+    /// Draws the next state of `src` from the distribution of its outgoing
+    /// edges, self-loops included. Returns `None` if `src` has no outgoing edge
+    /// or if their weights do not sum to a positive number.
+    pub fn sample_dest<R: Rng>(&self, src: &N, rng: &mut R) -> Option<N> {
+        let edges = self.adj_list.get(src)?;
+        let total: f64 = edges.iter().map(|edge| edge.weight).sum();
+        if !total.is_finite() || total <= 0.0 {
+            return None;
+        }
+
+        let mut threshold = random_uniform_range(rng, 0.0, total);
+        for edge in edges {
+            if threshold < edge.weight {
+                return Some(edge.dest.clone());
+            }
+            threshold -= edge.weight;
+        }
+
+        // Rounding errors can leave a tiny remainder after the last edge
+        edges.last().map(|edge| edge.dest.clone())
+    }
+
+    /// Probability of going from `src` to `dest`, i.e. the sum of the weights
+    /// of the edges between them, 0 if there is none.
+    pub fn transition_prob(&self, src: &N, dest: &N) -> f64 {
+        self.adj_list.get(src).map_or(0.0, |edges| {
+            edges
+                .iter()
+                .filter(|edge| edge.dest == *dest)
+                .map(|edge| edge.weight)
+                .sum()
+        })
+    }
+
+    /// Graph of the transitions over `steps` steps, whose weights are the
+    /// transition matrix raised to the power `steps`.
+    pub fn power(&self, steps: u32) -> MarkovGraph<N> {
+        let nodes = self.get_all_nodes();
+        let matrix: Vec<Vec<f64>> = nodes
+            .iter()
+            .map(|src| {
+                nodes
+                    .iter()
+                    .map(|dest| self.transition_prob(src, dest))
+                    .collect()
+            })
+            .collect();
+
+        // Exponentiation by squaring
+        let mut result: Vec<Vec<f64>> = (0..nodes.len())
+            .map(|i| {
+                (0..nodes.len())
+                    .map(|j| if i == j { 1.0 } else { 0.0 })
+                    .collect()
+            })
+            .collect();
+        let mut base = matrix;
+        let mut k = steps;
+        while k > 0 {
+            if k & 1 == 1 {
+                result = multiply(&result, &base);
+            }
+            base = multiply(&base, &base);
+            k >>= 1;
+        }
+
+        let mut graph = MarkovGraph::new();
+        for (src, row) in nodes.iter().zip(&result) {
+            for (dest, &weight) in nodes.iter().zip(row) {
+                if weight > 0.0 {
+                    graph.add_edge(src.clone(), dest.clone(), weight);
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Checks that the graph is a valid transition matrix: weights are
+    /// probabilities, the outgoing weights of each node sum to one and every
+    /// destination has outgoing edges itself.
+    pub fn validate(&self) -> Result<(), String>
+    where
+        N: fmt::Display,
+    {
+        if self.adj_list.is_empty() {
+            return Err(String::from("graph has no edge"));
+        }
+
+        for (src, edges) in &self.adj_list {
             for edge in edges {
-                if edge.weight == weight {
-                    return Some(edge.dest.clone());
+                if !(0.0..=1.0).contains(&edge.weight) {
+                    return Err(format!(
+                        "weight of {} -> {} must be between 0 and 1, got {}",
+                        src, edge.dest, edge.weight
+                    ));
+                }
+                if !self.adj_list.contains_key(&edge.dest) {
+                    return Err(format!(
+                        "{} has no outgoing edge (reached from {})",
+                        edge.dest, src
+                    ));
                 }
             }
+
+            let total: f64 = edges.iter().map(|edge| edge.weight).sum();
+            if (total - 1.0).abs() > ROW_SUM_TOLERANCE {
+                return Err(format!(
+                    "outgoing weights of {} must sum to 1, got {}",
+                    src, total
+                ));
+            }
         }
-        None
+
+        Ok(())
     }
 
     pub fn get_all_nodes(&self) -> Vec<N> {
@@ -49,7 +170,7 @@ where
 
 impl<N: fmt::Display> fmt::Display for MarkovGraph<N>
 where
-    N: Clone + Eq + PartialEq + std::hash::Hash,
+    N: Clone + Ord,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for (src, edges) in &self.adj_list {
@@ -63,26 +184,76 @@ where
     }
 }
 
-pub fn read_graph_from_file<N>(filename: &str) -> MarkovGraph<N>
+fn multiply(a: &[Vec<f64>], b: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    a.iter()
+        .map(|row| {
+            (0..b.len())
+                .map(|j| row.iter().zip(b).map(|(x, b_row)| x * b_row[j]).sum())
+                .collect()
+        })
+        .collect()
+}
+
+/// Reads a graph with one `<src> <dest> <weight>` edge per line. Blank lines
+/// are skipped.
+pub fn read_graph_from_file<N>(filename: &str) -> Result<MarkovGraph<N>, Error>
 where
-    N: Clone + Eq + std::hash::Hash + std::str::FromStr,
-    <N as std::str::FromStr>::Err: std::fmt::Debug,
+    N: Clone + Ord + std::str::FromStr,
+    <N as std::str::FromStr>::Err: fmt::Display,
 {
-    let file = File::open(filename).unwrap();
+    let file = File::open(filename).map_err(|e| Error::io(filename, e))?;
     let reader = BufReader::new(file);
 
     let mut graph = MarkovGraph::new();
 
-    for line in reader.lines() {
-        let line = line.unwrap();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| Error::io(filename, e))?;
+        let malformed = |message: String| Error::Graph {
+            path: filename.to_string(),
+            line: i + 1,
+            message,
+        };
+
         let parts: Vec<&str> = line.split_whitespace().collect();
+        let (src, dest, weight) = match parts[..] {
+            [] => continue,
+            [src, dest, weight] => (src, dest, weight),
+            _ => {
+                return Err(malformed(format!(
+                    "expected `<src> <dest> <weight>`, got {:?}",
+                    line
+                )))
+            }
+        };
 
-        let src: N = parts[0].parse().unwrap();
-        let dest: N = parts[1].parse().unwrap();
-        let weight: f64 = parts[2].parse().unwrap();
+        let src: N = src.parse().map_err(|e| malformed(format!("{}", e)))?;
+        let dest: N = dest.parse().map_err(|e| malformed(format!("{}", e)))?;
+        let weight: f64 = weight
+            .parse()
+            .map_err(|_| malformed(format!("invalid weight: {}", weight)))?;
 
         graph.add_edge(src, dest, weight);
     }
 
-    graph
+    Ok(graph)
+}
+
+/// Writes a graph in the format read by `read_graph_from_file`, one
+/// `<src> <dest> <weight>` edge per line.
+pub fn write_graph_to_file<N>(graph: &MarkovGraph<N>, filename: &str) -> Result<(), Error>
+where
+    N: Clone + Ord + fmt::Display,
+{
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+
+        for (src, edges) in &graph.adj_list {
+            for edge in edges {
+                writeln!(writer, "{} {} {}", src, edge.dest, edge.weight)?;
+            }
+        }
+
+        writer.flush()
+    };
+    write().map_err(|e| Error::io(filename, e))
 }
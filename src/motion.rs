@@ -0,0 +1,220 @@
+use crate::context::{ContextSet, HeadingModel};
+use crate::error::Error;
+use crate::geometry::{Degrees, Point};
+use crate::particle::Particle;
+use crate::port::Destination;
+use crate::random_generator::{random_uniform, random_uniform_range};
+use rand::Rng;
+
+/// Motion of the vessels in each context: the speed and heading models of
+/// the contexts, and the ports the directed heading models steer from and
+/// to. The particle filter moves its particles with it and the simulator its
+/// vessels.
+#[derive(Debug, Clone)]
+pub struct MotionModel {
+    pub contexts: ContextSet,
+    pub home_port: Point,
+    pub destination: Destination,
+}
+
+impl MotionModel {
+    /// Moves a particle in its context over `time_diff`. Fails if the context
+    /// of the particle is not in the context set.
+    pub fn update<R: Rng>(
+        &self,
+        particle: &Particle,
+        time_diff: f64,
+        rng: &mut R,
+    ) -> Result<Particle, Error> {
+        let context = self.contexts.lookup(&particle.context)?;
+
+        // Update speed
+        let new_speed = context.speed.sample(rng)?;
+        let distance = new_speed * time_diff;
+
+        // Update heading
+        let new_heading = self.generate_new_random_heading(particle, context.heading, rng);
+
+        // Update direction
+        let new_dir = Point::from_bearing(new_heading);
+
+        // Update position
+        let new_pos = Point {
+            x: particle.pos.x + (distance * new_dir.x),
+            y: particle.pos.y + (distance * new_dir.y),
+        };
+
+        Ok(Particle {
+            pos: new_pos,
+            direction: new_dir,
+            heading: new_heading,
+            turn: new_heading.difference(particle.heading),
+            speed: new_speed,
+            weight: particle.weight,
+            context: particle.context.clone(),
+            lineage: particle.lineage,
+        })
+    }
+
+    fn generate_new_random_heading<R: Rng>(
+        &self,
+        particle: &Particle,
+        model: HeadingModel,
+        rng: &mut R,
+    ) -> Degrees {
+        let jitter = model.jitter();
+
+        // Directed particles steer away from the home port or toward their
+        // destination port
+        let port = match model {
+            HeadingModel::TowardPort { .. } => self.destination.pos(particle.pos),
+            _ => self.home_port,
+        };
+
+        if particle.pos.x == port.x && particle.pos.y == port.y {
+            let change = random_uniform_range(rng, -jitter, jitter);
+
+            return (particle.heading + Degrees(change)).wrap();
+        }
+
+        // Signed turn from the heading of the particle to the direction of
+        // the port, positive when the port is on the starboard side
+        let port_turn = particle.pos.bearing_to(port).difference(particle.heading).0;
+
+        let change: f64 = match model {
+            HeadingModel::RandomWalk { .. } => random_uniform_range(rng, -jitter, jitter),
+            HeadingModel::AwayFromPort { .. } => {
+                // Turn away from the home port
+                let (low, high) = if port_turn > 0.0 {
+                    (-jitter, 0.0)
+                } else {
+                    (0.0, jitter)
+                };
+
+                random_uniform_range(rng, low, high)
+            }
+            HeadingModel::TowardPort { .. } => {
+                // Turn toward the destination port
+                let (low, high) = if port_turn > 0.0 {
+                    (0.0, jitter)
+                } else {
+                    (-jitter, 0.0)
+                };
+
+                random_uniform_range(rng, low, high)
+            }
+            HeadingModel::Circling { rate, .. } => {
+                // Keep turning the same way, particles that did not turn yet
+                // pick a way at random
+                let way = if particle.turn.0 != 0.0 {
+                    particle.turn.0.signum()
+                } else if random_uniform(rng) < 0.5 {
+                    -1.0
+                } else {
+                    1.0
+                };
+
+                way * rate + random_uniform_range(rng, -jitter, jitter)
+            }
+        };
+
+        (particle.heading + Degrees(change)).wrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RunConfig;
+    use crate::context::ContextConfig;
+    use crate::distribution::SpeedDistribution;
+    use crate::gear::builtin_profile;
+    use crate::particle::ParticleContextType;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn motion(contexts: ContextSet) -> MotionModel {
+        MotionModel {
+            contexts,
+            home_port: Point { x: 0.0, y: 0.0 },
+            destination: Destination::Port(Point { x: 0.0, y: 0.0 }),
+        }
+    }
+
+    fn particle(pos: Point, heading: Degrees) -> Particle {
+        Particle {
+            pos,
+            direction: Point::from_bearing(heading),
+            heading,
+            turn: Degrees(0.0),
+            speed: 0.0,
+            weight: 1.0,
+            context: ParticleContextType::fishing(),
+            lineage: 0,
+        }
+    }
+
+    #[test]
+    fn a_particle_heading_east_moves_east() {
+        let model = motion(ContextSet::new(vec![ContextConfig {
+            name: ParticleContextType::fishing(),
+            labels: vec![],
+            speed: SpeedDistribution::Normal {
+                mean: 1.0,
+                std_dev: 0.0,
+            },
+            heading: HeadingModel::RandomWalk { jitter: 0.0 },
+        }]));
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+
+        let start = Point { x: 5.0, y: 3.0 };
+        let moved = model
+            .update(&particle(start, Degrees(90.0)), 10.0, &mut rng)
+            .unwrap();
+
+        assert!((moved.pos.x - 15.0).abs() < 1e-9, "moved to {}", moved.pos);
+        assert!((moved.pos.y - 3.0).abs() < 1e-9, "moved to {}", moved.pos);
+        assert!((moved.heading.0 - 90.0).abs() < 1e-9);
+        assert!(moved.turn.0.abs() < 1e-9);
+    }
+
+    #[test]
+    fn fishing_turns_stay_within_the_jitter() {
+        let default_contexts = RunConfig::default().context_set();
+        let trawler_contexts = ContextSet::new(builtin_profile("trawler").unwrap().contexts);
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+
+        for contexts in [default_contexts, trawler_contexts] {
+            let jitter = contexts
+                .lookup(&ParticleContextType::fishing())
+                .unwrap()
+                .heading
+                .jitter();
+            assert!(jitter > 0.0);
+            let model = motion(contexts);
+
+            // Headings on both sides of north, where the turns wrap around
+            for heading in [0.0, 5.0, 90.0, 200.0, 355.0] {
+                let start = particle(
+                    Point {
+                        x: 1000.0,
+                        y: -500.0,
+                    },
+                    Degrees(heading),
+                );
+                for _ in 0..500 {
+                    let moved = model.update(&start, 60.0, &mut rng).unwrap();
+                    let turn = moved.heading.difference(start.heading).0;
+                    assert!(
+                        turn.abs() <= jitter,
+                        "turn of {} from {} beyond the jitter {}",
+                        turn,
+                        start.heading,
+                        jitter
+                    );
+                    assert!((moved.turn.0 - turn).abs() < 1e-9);
+                }
+            }
+        }
+    }
+}
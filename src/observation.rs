@@ -1,18 +1,27 @@
+use crate::collapse::CollapsePolicy;
+use crate::context::ContextSet;
+use crate::error::Error;
+use crate::gap::GapPolicy;
 use crate::geometry::Point;
-use crate::particle::ParticleContextType;
+pub use crate::particle::ParticleContextType;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Observation {
     pub pos: Point,
     pub time: f64,
+    /// Compass bearing in degrees, see `Degrees`
     pub heading: f64,
     pub speed: f64,
+    /// Signed change of heading since the previous observation, in degrees
+    pub turn: f64,
+    pub distance_to_shore: f64,
     pub context: ParticleContextType,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct AisRecord {
     pub id: String,
@@ -28,34 +37,183 @@ pub struct AisRecord {
     pub euc_speed: f64,
     pub distanceToShore: f64,
     pub label: String,
+    /// Gear type of the vessel, see `GearConfig`
+    #[serde(default)]
+    pub gear: Option<String>,
+}
+
+/// One line of a result file written by the `run` command
+#[derive(Debug, Deserialize)]
+pub struct ResultRecord {
+    /// Vessel of the line, in the result files of `--by-vessel` runs
+    #[serde(default)]
+    pub id: Option<String>,
+    pub x: f64,
+    pub y: f64,
+    pub time: f64,
+    pub heading: f64,
+    pub speed: f64,
+    pub context: ParticleContextType,
+}
+
+/// Decoded observations of one vessel of a result file, with its `id` if
+/// the file has an `id` column
+pub type ResultVessel = (Option<String>, Vec<Observation>);
+
+/// Probability of each context at every observation of a trajectory
+#[derive(Debug, Clone, Default)]
+pub struct ContextPosteriors {
+    pub contexts: Vec<ParticleContextType>,
+    /// `probs[i][j]` is the probability of observation `i` being in
+    /// `contexts[j]`
+    pub probs: Vec<Vec<f64>>,
+}
+
+/// Event of a decoder at one observation, written in the `event` column of
+/// the result files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StepEvent {
+    #[default]
+    None,
+    /// The particle weights collapsed and were recovered with this policy
+    Collapse(CollapsePolicy),
+    /// The observation comes after a gap, handled with this policy
+    Gap(GapPolicy),
+}
+
+impl AisRecord {
+    /// Reads an AIS file. The records are sorted by `t`, which compares
+    /// correctly as long as timestamps are written in ISO 8601 format;
+    /// records with the same `t` keep the order of the file.
+    pub fn from_csv(filename: &str) -> Result<Vec<AisRecord>, Error> {
+        let mut records = AisRecord::read_csv(filename)?;
+        AisRecord::sort_by_time(&mut records);
+        Ok(records)
+    }
+
+    /// Writes records in the AIS CSV format read by `from_csv`.
+    pub fn to_csv(filename: &str, records: &[AisRecord]) -> Result<(), Error> {
+        let write = || -> Result<(), csv::Error> {
+            let mut wtr = csv::Writer::from_path(filename)?;
+            for record in records {
+                wtr.serialize(record)?;
+            }
+            wtr.flush()?;
+            Ok(())
+        };
+        write().map_err(|e| Error::csv(filename, e))
+    }
+
+    /// Reads a CSV file containing the records of several vessels and groups
+    /// them by vessel `id`. Vessels are returned in order of first appearance
+    /// and the records of each vessel are sorted by `t`, as in `from_csv`.
+    pub fn from_csv_by_vessel(filename: &str) -> Result<Vec<(String, Vec<AisRecord>)>, Error> {
+        let mut vessels: Vec<(String, Vec<AisRecord>)> = Vec::new();
+        let mut vessel_indices: HashMap<String, usize> = HashMap::new();
+
+        for record in AisRecord::read_csv(filename)? {
+            let index = *vessel_indices.entry(record.id.clone()).or_insert_with(|| {
+                vessels.push((record.id.clone(), Vec::new()));
+                vessels.len() - 1
+            });
+            vessels[index].1.push(record);
+        }
+
+        for (_, records) in &mut vessels {
+            AisRecord::sort_by_time(records);
+        }
+        Ok(vessels)
+    }
+
+    /// Records of an AIS file in the order of the file
+    fn read_csv(filename: &str) -> Result<Vec<AisRecord>, Error> {
+        let mut rdr = csv::Reader::from_path(filename).map_err(|e| Error::csv(filename, e))?;
+        rdr.deserialize()
+            .collect::<Result<Vec<AisRecord>, _>>()
+            .map_err(|e| Error::csv(filename, e))
+    }
+
+    /// Stable sort by `t`, so that records with the same timestamp keep the
+    /// order of the file
+    fn sort_by_time(records: &mut [AisRecord]) {
+        records.sort_by(|a, b| a.t.cmp(&b.t));
+    }
 }
 
 impl Observation {
-    pub fn from_csv(filename: &str) -> Result<Vec<Observation>, csv::Error> {
-        let mut observations: Vec<Observation> = Vec::new();
+    /// Reads an AIS file. Labels are mapped to the contexts of the set.
+    pub fn from_csv(filename: &str, contexts: &ContextSet) -> Result<Vec<Observation>, Error> {
+        Ok(Observation::from_records(
+            &AisRecord::from_csv(filename)?,
+            contexts,
+        ))
+    }
+
+    /// Reads a CSV file containing the records of several vessels, see
+    /// `AisRecord::from_csv_by_vessel`.
+    pub fn from_csv_by_vessel(
+        filename: &str,
+        contexts: &ContextSet,
+    ) -> Result<Vec<(String, Vec<Observation>)>, Error> {
+        Ok(AisRecord::from_csv_by_vessel(filename)?
+            .into_iter()
+            .map(|(id, records)| (id, Observation::from_records(&records, contexts)))
+            .collect())
+    }
+
+    pub fn from_records(records: &[AisRecord], contexts: &ContextSet) -> Vec<Observation> {
+        records
+            .iter()
+            .map(|record| Observation::from_record(record, contexts))
+            .collect()
+    }
 
-        let mut rdr = csv::Reader::from_path(filename)?;
+    /// Reads a result file and groups its lines by vessel `id`, in order of
+    /// first appearance. The lines of a result file without an `id` column,
+    /// written for a single vessel, are a single group without an id.
+    pub fn from_result_csv_by_vessel(filename: &str) -> Result<Vec<ResultVessel>, Error> {
+        let mut vessels: Vec<ResultVessel> = Vec::new();
+        let mut vessel_indices: HashMap<Option<String>, usize> = HashMap::new();
+
+        let mut rdr = csv::Reader::from_path(filename).map_err(|e| Error::csv(filename, e))?;
         for result in rdr.deserialize() {
-            let record: AisRecord = result?;
-            let context = match &*record.label {
-                "01-sailing" => ParticleContextType::GoFishing,
-                "02-fishing" => ParticleContextType::Fishing,
-                "03-sailing" => ParticleContextType::GoToPort,
-                _ => ParticleContextType::GoFishing, // Default case
-            };
-            let observation = Observation {
+            let record: ResultRecord = result.map_err(|e| Error::csv(filename, e))?;
+            let index = *vessel_indices.entry(record.id.clone()).or_insert_with(|| {
+                vessels.push((record.id.clone(), Vec::new()));
+                vessels.len() - 1
+            });
+            vessels[index].1.push(Observation {
                 pos: Point {
                     x: record.x,
                     y: record.y,
                 },
-                time: record.time_gap,
-                heading: record.bearing,
-                speed: record.euc_speed,
-                context,
-            };
-            observations.push(observation);
+                time: record.time,
+                heading: record.heading,
+                speed: record.speed,
+                turn: 0.0,
+                distance_to_shore: f64::NAN,
+                context: record.context,
+            });
+        }
+
+        Ok(vessels)
+    }
+}
+
+impl Observation {
+    fn from_record(record: &AisRecord, contexts: &ContextSet) -> Self {
+        Observation {
+            pos: Point {
+                x: record.x,
+                y: record.y,
+            },
+            time: record.time_gap,
+            heading: record.bearing,
+            speed: record.euc_speed,
+            turn: record.signed_turn,
+            distance_to_shore: record.distanceToShore,
+            context: contexts.context_of(&record.label),
         }
-        Ok(observations)
     }
 }
 
@@ -70,3 +228,72 @@ impl Observation {
 //         Ok(())
 //     }
 // }
+
+impl fmt::Display for StepEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepEvent::None => Ok(()),
+            StepEvent::Collapse(policy) => write!(f, "collapse-{}", policy),
+            StepEvent::Gap(policy) => write!(f, "gap-{}", policy),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, t: &str, x: f64) -> AisRecord {
+        AisRecord {
+            id: String::from(id),
+            t: String::from(t),
+            longitude: 0.0,
+            latitude: 0.0,
+            x,
+            y: 0.0,
+            signed_turn: 0.0,
+            bearing: 0.0,
+            time_gap: 0.0,
+            distance_gap: 0.0,
+            euc_speed: 0.0,
+            distanceToShore: 0.0,
+            label: String::from("02-fishing"),
+            gear: None,
+        }
+    }
+
+    #[test]
+    fn records_are_read_sorted_by_time() {
+        let path = std::env::temp_dir().join(format!(
+            "observation_unsorted_records_{}.csv",
+            std::process::id()
+        ));
+        let path = path.to_string_lossy();
+        AisRecord::to_csv(
+            &path,
+            &[
+                record("b", "2021-01-01T10:00:00", 1.0),
+                record("a", "2021-01-01T12:00:00", 2.0),
+                record("a", "2021-01-01T09:00:00", 3.0),
+                record("b", "2021-01-01T08:00:00", 4.0),
+                record("a", "2021-01-01T09:00:00", 5.0),
+            ],
+        )
+        .unwrap();
+
+        let records = AisRecord::from_csv(&path);
+        let vessels = AisRecord::from_csv_by_vessel(&path);
+        std::fs::remove_file(path.as_ref()).unwrap();
+
+        // Records with the same time keep the order of the file
+        let xs = |records: &[AisRecord]| records.iter().map(|r| r.x).collect::<Vec<f64>>();
+        assert_eq!(xs(&records.unwrap()), vec![4.0, 3.0, 5.0, 1.0, 2.0]);
+
+        let vessels = vessels.unwrap();
+        assert_eq!(vessels.len(), 2);
+        assert_eq!(vessels[0].0, "b");
+        assert_eq!(xs(&vessels[0].1), vec![4.0, 1.0]);
+        assert_eq!(vessels[1].0, "a");
+        assert_eq!(xs(&vessels[1].1), vec![3.0, 5.0, 2.0]);
+    }
+}
@@ -1,69 +1,77 @@
-use crate::geometry::Point;
-use crate::observation::Observation;
-use serde::{Deserialize, Serialize};
+use crate::geometry::{Degrees, Point};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Particle {
     pub pos: Point,
     pub direction: Point,
-    pub heading: f64,
+    /// Compass bearing of the motion of the particle
+    pub heading: Degrees,
+    /// Heading change of the last step, positive clockwise
+    pub turn: Degrees,
     pub speed: f64,
     pub weight: f64,
     pub context: ParticleContextType,
-    pub memory: Vec<ParticleContextType>,
+    /// Last node of the particle in the genealogy of the filter, see `Lineage`
+    pub lineage: usize,
 }
 
-#[derive(Debug, Serialize, Clone)]
-pub struct ParticleHistory {
-    pub particles: Vec<Particle>,
-}
+/// Name of a context state, e.g. `Fishing`. The context states are defined
+/// by the run configuration, see `ContextSet`; names are shared so that
+/// contexts are cheap to clone, and freed with the last context using them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ParticleContextType(Arc<str>);
+
+impl ParticleContextType {
+    /// Contexts of the default context set
+    pub fn go_fishing() -> ParticleContextType {
+        ParticleContextType(Arc::from("GoFishing"))
+    }
+
+    pub fn fishing() -> ParticleContextType {
+        ParticleContextType(Arc::from("Fishing"))
+    }
+
+    pub fn go_to_port() -> ParticleContextType {
+        ParticleContextType(Arc::from("GoToPort"))
+    }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum ParticleContextType {
-    GoFishing,
-    Fishing,
-    GoToPort,
+    pub fn name(&self) -> &str {
+        &self.0
+    }
 }
 
 impl FromStr for ParticleContextType {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "GoFishing" => Ok(ParticleContextType::GoFishing),
-            "Fishing" => Ok(ParticleContextType::Fishing),
-            "GoToPort" => Ok(ParticleContextType::GoToPort),
-            _ => Err(format!("Invalid particle context type: {}", s)),
+        // Names are written in graph files, separated by whitespace, and in
+        // CSV files
+        if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == ',') {
+            return Err(format!("Invalid particle context type: {:?}", s));
         }
+        Ok(ParticleContextType(Arc::from(s)))
     }
 }
 
 impl fmt::Display for ParticleContextType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            ParticleContextType::GoFishing => write!(f, "GoFishing"),
-            ParticleContextType::Fishing => write!(f, "Fishing"),
-            ParticleContextType::GoToPort => write!(f, "GoToPort"),
-        }
+        write!(f, "{}", self.0)
     }
 }
 
-impl fmt::Display for ParticleHistory {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for particle in &self.particles {
-            write!(
-                f,
-                "{},{},{:.2},{:.2},{:.2},{}\n",
-                particle.pos,
-                particle.direction,
-                particle.heading,
-                particle.speed,
-                particle.weight,
-                particle.context
-            )?;
-        }
-        Ok(())
+impl Serialize for ParticleContextType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ParticleContextType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(de::Error::custom)
     }
 }
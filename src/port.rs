@@ -0,0 +1,153 @@
+use crate::error::Error;
+use crate::geometry::Point;
+use crate::observation::Observation;
+use serde::{Deserialize, Serialize};
+
+/// Ports the vessels leave from and return to. Without a port file, the
+/// first observation of the trajectory is used as the only port.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortsConfig {
+    /// CSV file of the port registry
+    pub path: Option<String>,
+    /// Name of the port the vessel leaves from, the port nearest to the first
+    /// observation by default
+    pub home: Option<String>,
+    /// Name of the port the vessel returns to. By default, particles going to
+    /// port steer toward the port nearest to them
+    pub destination: Option<String>,
+}
+
+/// Port particles going to port steer toward
+#[derive(Debug, Clone)]
+pub enum Destination {
+    Port(Point),
+    NearestPort(PortRegistry),
+}
+
+/// A port where vessels leave from and return to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Port {
+    pub name: String,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// List of known ports, read from a CSV file with `name`, `x` and `y`
+/// columns. Coordinates are in the same projection as the `x` and `y` columns
+/// of the AIS files.
+#[derive(Debug, Clone, Default)]
+pub struct PortRegistry {
+    ports: Vec<Port>,
+}
+
+impl Port {
+    pub fn pos(&self) -> Point {
+        Point {
+            x: self.x,
+            y: self.y,
+        }
+    }
+}
+
+impl PortRegistry {
+    pub fn from_csv(filename: &str) -> Result<PortRegistry, Error> {
+        let mut rdr = csv::Reader::from_path(filename).map_err(|e| Error::csv(filename, e))?;
+        let ports = rdr
+            .deserialize()
+            .collect::<Result<Vec<Port>, _>>()
+            .map_err(|e| Error::csv(filename, e))?;
+
+        Ok(PortRegistry { ports })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Port> {
+        self.ports.iter().find(|port| port.name == name)
+    }
+
+    pub fn nearest(&self, pos: Point) -> Option<&Port> {
+        self.ports
+            .iter()
+            .min_by(|a, b| (a.pos() - pos).norm().total_cmp(&(b.pos() - pos).norm()))
+    }
+}
+
+impl PortsConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        let Some((path, registry)) = self.path.as_ref().zip(self.read_registry()?) else {
+            if self.home.is_some() || self.destination.is_some() {
+                return Err(Error::Config(String::from(
+                    "ports.home and ports.destination require a ports.path file",
+                )));
+            }
+            return Ok(());
+        };
+
+        for name in self.home.iter().chain(self.destination.iter()) {
+            if registry.get(name).is_none() {
+                return Err(Error::Config(format!(
+                    "port {} is not in the port file {}",
+                    name, path
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the port file, which must have a port. `None` without a port
+    /// file.
+    pub fn read_registry(&self) -> Result<Option<PortRegistry>, Error> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+
+        let registry = PortRegistry::from_csv(path)?;
+        if registry.is_empty() {
+            return Err(Error::Config(format!("port file {} has no port", path)));
+        }
+        Ok(Some(registry))
+    }
+
+    /// Picks the home port and the destination of a trajectory among the
+    /// ports of the registry read by `read_registry`.
+    pub fn resolve(
+        &self,
+        registry: Option<&PortRegistry>,
+        observations: &[Observation],
+    ) -> (Point, Destination) {
+        let first_pos = observations[0].pos;
+
+        let Some(registry) = registry else {
+            return (first_pos, Destination::Port(first_pos));
+        };
+
+        let home = match &self.home {
+            Some(name) => registry.get(name),
+            None => registry.nearest(first_pos),
+        }
+        .map_or(first_pos, Port::pos);
+
+        let destination = match &self.destination {
+            Some(name) => Destination::Port(registry.get(name).map_or(home, Port::pos)),
+            None => Destination::NearestPort(registry.clone()),
+        };
+
+        (home, destination)
+    }
+}
+
+impl Destination {
+    /// Position of the destination port of a particle at `pos`. The registry
+    /// of `NearestPort` is not empty, see `PortsConfig::resolve`; an empty
+    /// one gives `pos` itself, i.e. no direction to steer to.
+    pub fn pos(&self, pos: Point) -> Point {
+        match self {
+            Destination::Port(port) => *port,
+            Destination::NearestPort(registry) => registry.nearest(pos).map_or(pos, Port::pos),
+        }
+    }
+}
@@ -1,30 +1,52 @@
-use rand::{distributions::Uniform, thread_rng, Rng};
-use rand_distr::{Distribution, Normal};
+use rand::{distributions::Uniform, Rng};
+use rand_distr::StandardNormal;
 
-pub fn random_uniform() -> f64 {
-    let mut rng = thread_rng();
+pub fn random_uniform<R: Rng>(rng: &mut R) -> f64 {
     let uniform = Uniform::new(0.0f64, 1.0f64);
 
     rng.sample(uniform)
 }
 
-pub fn random_uniform_range(low: f64, high: f64) -> f64 {
-    let mut rng = thread_rng();
+/// Uniform draw in `[low, high)`. An empty range, e.g. a heading jitter of 0,
+/// gives `low` without drawing.
+pub fn random_uniform_range<R: Rng>(rng: &mut R, low: f64, high: f64) -> f64 {
+    if low >= high {
+        return low;
+    }
     let uniform = Uniform::new(low, high);
 
     rng.sample(uniform)
 }
 
-pub fn random_usize_uniform_range(low: usize, high: usize) -> usize {
-    let mut rng = thread_rng();
-    let uniform = Uniform::new(low, high);
+/// Normal draw of mean `mean` and standard deviation `std_dev`, which is not
+/// checked: a negative one mirrors the draw around the mean.
+pub fn random_normal<R: Rng>(rng: &mut R, mean: f64, std_dev: f64) -> f64 {
+    let z: f64 = rng.sample(StandardNormal);
 
-    rng.sample(uniform)
+    mean + std_dev * z
 }
 
-pub fn random_normal(mean: f64, std_dev: f64) -> f64 {
-    let mut rng = thread_rng();
-    let normal = Normal::new(mean, std_dev).unwrap();
+/// Largest seed value. Seeds are kept below 2^63 so that they can be written
+/// as TOML integers in the echoed run configuration.
+pub const MAX_SEED: u64 = i64::MAX as u64;
+
+/// Picks a seed for runs that were not given one.
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen_range(0..=MAX_SEED)
+}
 
-    normal.sample(&mut rng)
+/// Derives the seed of an independent random stream from a base seed and a
+/// key, e.g. a file name or a vessel id. The derived seed only depends on the
+/// key and not on the order in which the streams are created.
+pub fn derive_seed(seed: u64, key: &str) -> u64 {
+    // FNV-1a hash of the key
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    // SplitMix64 finalizer to spread the bits of the combined value
+    let mut z = (seed ^ hash).wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (z ^ (z >> 31)) & MAX_SEED
 }
@@ -0,0 +1,275 @@
+use crate::random_generator::{random_uniform, random_uniform_range};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Algorithm drawing the offspring of the particles from their weights. All
+/// schemes give each particle `N * w` offspring on average; systematic,
+/// stratified and residual resampling do it with less variance than
+/// multinomial resampling.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResamplingScheme {
+    /// `N` independent draws, `O(N log N)`
+    #[default]
+    Multinomial,
+    /// One uniform draw shifted by `1 / N` for each offspring, `O(N)`
+    Systematic,
+    /// One uniform draw in each of the `N` strata of width `1 / N`, `O(N)`
+    Stratified,
+    /// `floor(N * w)` offspring for each particle, the remaining ones drawn
+    /// from the residual weights with systematic resampling, so that each
+    /// particle gets `floor(N * w)` or `ceil(N * w)` offspring
+    Residual,
+}
+
+impl ResamplingScheme {
+    /// Draws the indices of `n` particles with probability proportional to
+    /// `weights`.
+    pub fn resample<R: Rng>(&self, weights: &[f64], n: usize, rng: &mut R) -> Vec<usize> {
+        if weights.is_empty() {
+            return Vec::new();
+        }
+
+        match self {
+            ResamplingScheme::Multinomial => multinomial(weights, n, rng),
+            ResamplingScheme::Systematic => {
+                let offset = random_uniform(rng);
+                ordered(weights, n, |i| (i as f64 + offset) / n as f64)
+            }
+            ResamplingScheme::Stratified => {
+                ordered(weights, n, |i| (i as f64 + random_uniform(rng)) / n as f64)
+            }
+            ResamplingScheme::Residual => residual(weights, n, rng),
+        }
+    }
+}
+
+/// Running sums of the weights.
+fn cumulative(weights: &[f64]) -> Vec<f64> {
+    weights
+        .iter()
+        .scan(0.0, |total, weight| {
+            *total += weight;
+            Some(*total)
+        })
+        .collect()
+}
+
+/// Index of the first particle whose cumulative weight reaches `threshold`.
+fn search(cumulative: &[f64], threshold: f64) -> usize {
+    cumulative
+        .partition_point(|&c| c < threshold)
+        .min(cumulative.len() - 1)
+}
+
+fn multinomial<R: Rng>(weights: &[f64], n: usize, rng: &mut R) -> Vec<usize> {
+    let cumulative = cumulative(weights);
+    let total = cumulative[cumulative.len() - 1];
+
+    (0..n)
+        .map(|_| {
+            let threshold = if total > 0.0 {
+                random_uniform_range(rng, 0.0, total)
+            } else {
+                0.0
+            };
+            search(&cumulative, threshold)
+        })
+        .collect()
+}
+
+/// Systematic and stratified resampling: `position(i)` is the position of the
+/// `i`-th offspring in `[0, 1)`, increasing with `i`, so that a single pass
+/// over the cumulative weights finds all the offspring.
+fn ordered(weights: &[f64], n: usize, mut position: impl FnMut(usize) -> f64) -> Vec<usize> {
+    let cumulative = cumulative(weights);
+    let total = cumulative[cumulative.len() - 1];
+
+    let mut indices = Vec::with_capacity(n);
+    let mut j = 0;
+    for i in 0..n {
+        let threshold = position(i) * total;
+        while j < cumulative.len() - 1 && cumulative[j] < threshold {
+            j += 1;
+        }
+        indices.push(j);
+    }
+
+    indices
+}
+
+fn residual<R: Rng>(weights: &[f64], n: usize, rng: &mut R) -> Vec<usize> {
+    let total: f64 = weights.iter().sum();
+    if !(total.is_finite() && total > 0.0) {
+        return multinomial(weights, n, rng);
+    }
+
+    let mut indices = Vec::with_capacity(n);
+    let mut residuals = Vec::with_capacity(weights.len());
+    for (i, weight) in weights.iter().enumerate() {
+        let expected = n as f64 * weight / total;
+        let copies = expected.floor() as usize;
+        indices.extend(std::iter::repeat_n(i, copies));
+        residuals.push(expected - copies as f64);
+    }
+
+    // The residual weights sum to the number of remaining offspring, so the
+    // systematic draws are 1 apart and each particle gets at most one of them
+    let remaining = n.saturating_sub(indices.len());
+    if remaining > 0 {
+        let offset = random_uniform(rng);
+        indices.extend(ordered(&residuals, remaining, |i| {
+            (i as f64 + offset) / remaining as f64
+        }));
+    }
+    indices.sort_unstable();
+
+    indices
+}
+
+impl FromStr for ResamplingScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "multinomial" => Ok(ResamplingScheme::Multinomial),
+            "systematic" => Ok(ResamplingScheme::Systematic),
+            "stratified" => Ok(ResamplingScheme::Stratified),
+            "residual" => Ok(ResamplingScheme::Residual),
+            _ => Err(format!("Invalid resampling scheme: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ResamplingScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResamplingScheme::Multinomial => write!(f, "multinomial"),
+            ResamplingScheme::Systematic => write!(f, "systematic"),
+            ResamplingScheme::Stratified => write!(f, "stratified"),
+            ResamplingScheme::Residual => write!(f, "residual"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    const WEIGHTS: [f64; 5] = [0.05, 0.1, 0.15, 0.3, 0.4];
+    const SCHEMES: [ResamplingScheme; 4] = [
+        ResamplingScheme::Multinomial,
+        ResamplingScheme::Systematic,
+        ResamplingScheme::Stratified,
+        ResamplingScheme::Residual,
+    ];
+
+    fn offspring(indices: &[usize], nb_of_particles: usize) -> Vec<usize> {
+        let mut counts = vec![0; nb_of_particles];
+        for &i in indices {
+            counts[i] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn offspring_are_n_times_the_weight_on_average() {
+        let n = 12;
+        let nb_of_draws = 4000;
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+
+        for scheme in SCHEMES {
+            let mut total = [0usize; WEIGHTS.len()];
+            for _ in 0..nb_of_draws {
+                let indices = scheme.resample(&WEIGHTS, n, &mut rng);
+                assert_eq!(indices.len(), n);
+                for (total, count) in total.iter_mut().zip(offspring(&indices, WEIGHTS.len())) {
+                    *total += count;
+                }
+            }
+
+            for (total, weight) in total.iter().zip(WEIGHTS) {
+                let mean = *total as f64 / nb_of_draws as f64;
+                let expected = n as f64 * weight;
+                assert!(
+                    (mean - expected).abs() < 0.1,
+                    "{}: {} offspring on average, expected {}",
+                    scheme,
+                    mean,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn systematic_and_residual_offspring_are_within_floor_and_ceil() {
+        let n = 12;
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+
+        for scheme in [ResamplingScheme::Systematic, ResamplingScheme::Residual] {
+            for _ in 0..1000 {
+                let counts = offspring(&scheme.resample(&WEIGHTS, n, &mut rng), WEIGHTS.len());
+                for (count, weight) in counts.into_iter().zip(WEIGHTS) {
+                    let expected = n as f64 * weight;
+                    assert!(
+                        expected.floor() as usize <= count && count <= expected.ceil() as usize,
+                        "{}: {} offspring, expected {}",
+                        scheme,
+                        count,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_offspring_are_within_one_of_floor_and_ceil() {
+        // A particle whose weight spans k strata only partly can get one
+        // offspring less than the floor, or one more than the ceil, of
+        // N * w, e.g. none when both of its strata draw outside of it
+        let n = 12;
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+
+        for _ in 0..1000 {
+            let indices = ResamplingScheme::Stratified.resample(&WEIGHTS, n, &mut rng);
+            for (count, weight) in offspring(&indices, WEIGHTS.len()).into_iter().zip(WEIGHTS) {
+                let expected = n as f64 * weight;
+                assert!(
+                    expected.floor() as usize <= count + 1 && count <= expected.ceil() as usize + 1,
+                    "stratified: {} offspring, expected {}",
+                    count,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn offspring_of_weights_on_the_strata_are_exact() {
+        // N * w is an integer for every particle
+        let weights = [0.25, 0.5, 0.25];
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+
+        for scheme in [
+            ResamplingScheme::Systematic,
+            ResamplingScheme::Stratified,
+            ResamplingScheme::Residual,
+        ] {
+            for _ in 0..100 {
+                let indices = scheme.resample(&weights, 8, &mut rng);
+                assert_eq!(
+                    offspring(&indices, weights.len()),
+                    vec![2, 4, 2],
+                    "{}",
+                    scheme
+                );
+            }
+        }
+    }
+}
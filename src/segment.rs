@@ -0,0 +1,159 @@
+use crate::error::Error;
+use crate::observation::{ContextPosteriors, Observation, StepEvent};
+use crate::particle::ParticleContextType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+/// Phase of a trip: consecutive observations of a vessel decoded in the same
+/// context, e.g. "fished from 06:12 to 14:40 covering 38 km"
+#[derive(Debug, Clone, Serialize)]
+pub struct Segment {
+    pub id: String,
+    pub context: ParticleContextType,
+    /// Indices of the first and last observations of the segment among the
+    /// observations of the vessel, inclusive
+    pub start_index: usize,
+    pub end_index: usize,
+    pub start_time: String,
+    pub end_time: String,
+    /// Sum of the `time_gap` between the first and last observations
+    pub duration: f64,
+    /// Length of the track between the first and last observations, in the
+    /// unit of the `x` and `y` columns
+    pub distance: f64,
+    /// Mean of the observed speeds
+    pub mean_speed: f64,
+    /// Mean probability of the observations being in the context of the
+    /// segment
+    pub mean_confidence: f64,
+}
+
+/// Format of the segment files
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SegmentFormat {
+    Csv,
+    Json,
+}
+
+/// Merges the consecutive observations of a decoded trajectory that are in
+/// the same context into segments. Segments also end at the gaps of the
+/// track, whose duration and distance would be meaningless. `timestamps` are
+/// the `t` of the observations.
+pub fn segments(
+    id: &str,
+    states: &[Observation],
+    posteriors: &ContextPosteriors,
+    events: &[StepEvent],
+    timestamps: &[String],
+) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for i in 1..=states.len() {
+        let ends = i == states.len()
+            || states[i].context != states[i - 1].context
+            || matches!(events.get(i), Some(StepEvent::Gap(_)));
+        if ends {
+            segments.push(segment(id, start, i - 1, states, posteriors, timestamps));
+            start = i;
+        }
+    }
+
+    segments
+}
+
+fn segment(
+    id: &str,
+    start: usize,
+    end: usize,
+    states: &[Observation],
+    posteriors: &ContextPosteriors,
+    timestamps: &[String],
+) -> Segment {
+    let context = states[start].context.clone();
+    let observations = &states[start..=end];
+    let nb_of_observations = observations.len() as f64;
+
+    let column = posteriors.contexts.iter().position(|c| *c == context);
+    let mean_confidence = match column {
+        Some(j) => {
+            posteriors.probs[start..=end]
+                .iter()
+                .map(|probs| probs[j])
+                .sum::<f64>()
+                / nb_of_observations
+        }
+        None => f64::NAN,
+    };
+
+    Segment {
+        id: id.to_string(),
+        context,
+        start_index: start,
+        end_index: end,
+        start_time: timestamps.get(start).cloned().unwrap_or_default(),
+        end_time: timestamps.get(end).cloned().unwrap_or_default(),
+        duration: observations
+            .iter()
+            .skip(1)
+            .fold(0.0, |total, obs| total + obs.time),
+        distance: observations
+            .windows(2)
+            .fold(0.0, |total, pair| total + (pair[1].pos - pair[0].pos).norm()),
+        mean_speed: observations.iter().map(|obs| obs.speed).sum::<f64>() / nb_of_observations,
+        mean_confidence,
+    }
+}
+
+/// Writes segments next to a result file as `<result>.segments.csv` or
+/// `<result>.segments.json`.
+pub fn write_segments(
+    result_path: &str,
+    segments: &[Segment],
+    format: SegmentFormat,
+) -> Result<String, Error> {
+    let path = format!("{}.segments.{}", result_path, format);
+    match format {
+        SegmentFormat::Csv => {
+            let write = || -> Result<(), csv::Error> {
+                let mut wtr = csv::Writer::from_path(&path)?;
+                for segment in segments {
+                    wtr.serialize(segment)?;
+                }
+                wtr.flush()?;
+                Ok(())
+            };
+            write().map_err(|e| Error::csv(&path, e))?;
+        }
+        SegmentFormat::Json => {
+            let content =
+                serde_json::to_string_pretty(segments).map_err(|e| Error::io(&path, e.into()))?;
+            fs::write(&path, content).map_err(|e| Error::io(&path, e))?;
+        }
+    }
+
+    Ok(path)
+}
+
+impl FromStr for SegmentFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(SegmentFormat::Csv),
+            "json" => Ok(SegmentFormat::Json),
+            _ => Err(format!("Invalid segment format: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SegmentFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SegmentFormat::Csv => write!(f, "csv"),
+            SegmentFormat::Json => write!(f, "json"),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::error::Error;
+use crate::observation::Observation;
+use serde::{Deserialize, Serialize};
+
+/// Penalization of the weight of the particles of a context near the shore,
+/// as described in the Readme. The weight is multiplied by
+/// `f(d) = 1 - C * exp(-d / d0)` where `d` is the distance to the shore of the
+/// observation and `C = 1 - epsilon`, so that `f(0) = epsilon` and `f`
+/// tends to 1 far from the shore.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct ShorePenaltyConfig {
+    /// Weight scaling exactly on the shore
+    pub epsilon: f64,
+    /// Range `d0` of the penalization
+    pub d0: ShoreRange,
+}
+
+/// Range of the shore penalization, either a fixed distance or a distance
+/// derived from the distances to the shore of each trajectory.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum ShoreRange {
+    Fixed(f64),
+    Derived(DerivedShoreRange),
+}
+
+/// `d0 = offset + q-quantile of the distances to the shore of the trajectory`.
+/// E.g. a quantile of 0 and an offset of 4000 is 4000 plus the distance of
+/// the point of the trajectory closest to the shore, usually its port.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct DerivedShoreRange {
+    pub quantile: f64,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+/// Shore penalization with its range resolved for one trajectory
+#[derive(Debug, Clone, Copy)]
+pub struct ShorePenalty {
+    epsilon: f64,
+    d0: f64,
+}
+
+impl ShorePenaltyConfig {
+    pub fn validate(&self, name: &str) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&self.epsilon) {
+            return Err(Error::Config(format!(
+                "{}.epsilon must be between 0 and 1",
+                name
+            )));
+        }
+        match self.d0 {
+            ShoreRange::Fixed(d0) => {
+                if !(d0.is_finite() && d0 > 0.0) {
+                    return Err(Error::Config(format!("{}.d0 must be positive", name)));
+                }
+            }
+            ShoreRange::Derived(range) => {
+                if !(0.0..=1.0).contains(&range.quantile) {
+                    return Err(Error::Config(format!(
+                        "{}.d0.quantile must be between 0 and 1",
+                        name
+                    )));
+                }
+                if !(range.offset.is_finite() && range.offset >= 0.0) {
+                    return Err(Error::Config(format!(
+                        "{}.d0.offset must be non-negative",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the range of the penalization for a trajectory.
+    pub fn resolve(&self, observations: &[Observation]) -> ShorePenalty {
+        let d0 = match self.d0 {
+            ShoreRange::Fixed(d0) => d0,
+            ShoreRange::Derived(range) => {
+                let mut distances: Vec<f64> = observations
+                    .iter()
+                    .map(|observation| observation.distance_to_shore)
+                    .filter(|distance| distance.is_finite())
+                    .collect();
+                distances.sort_by(f64::total_cmp);
+
+                let quantile = match distances.len() {
+                    0 => 0.0,
+                    len => {
+                        let index = (range.quantile * (len - 1) as f64).round() as usize;
+                        distances[index].max(0.0)
+                    }
+                };
+                range.offset + quantile
+            }
+        };
+
+        ShorePenalty {
+            epsilon: self.epsilon,
+            // A range of 0 would penalize every point with the full epsilon
+            d0: d0.max(f64::MIN_POSITIVE),
+        }
+    }
+}
+
+impl ShorePenalty {
+    /// Weight scaling `f(d)` at distance `d` from the shore. Unknown distances
+    /// are not penalized.
+    pub fn weight_scale(&self, distance_to_shore: f64) -> f64 {
+        if distance_to_shore.is_nan() {
+            return 1.0;
+        }
+        let d = distance_to_shore.max(0.0);
+        1.0 - (1.0 - self.epsilon) * (-d / self.d0).exp()
+    }
+}
@@ -0,0 +1,262 @@
+use crate::context::ContextSet;
+use crate::error::Error;
+use crate::gap::nb_of_steps;
+use crate::geometry::{Degrees, Point};
+use crate::markov_graph::MarkovGraph;
+use crate::motion::MotionModel;
+use crate::observation::AisRecord;
+use crate::particle::{Particle, ParticleContextType};
+use crate::port::Destination;
+use crate::random_generator::{derive_seed, random_normal, random_uniform, random_uniform_range};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Time of the first record of the simulated trajectories, 2020-01-01T00:00:00
+/// in seconds since 1970-01-01
+const START_TIME: i64 = 1_577_836_800;
+
+/// Parameters of the simulated trajectories
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub nb_of_trajectories: usize,
+    /// Number of records of each trajectory
+    pub nb_of_observations: usize,
+    /// Time between two records outside of the gaps, in seconds
+    pub interval: f64,
+    /// Standard deviation of the noise added to the recorded positions
+    pub position_noise: f64,
+    /// Probability that a record is followed by a gap
+    pub gap_probability: f64,
+    /// Duration of the gaps, in seconds
+    pub gap_duration: f64,
+    /// Port where the vessels leave from and return to
+    pub port: Point,
+    /// Gear type written in the `gear` column
+    pub gear: Option<String>,
+    pub seed: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            nb_of_trajectories: 1,
+            nb_of_observations: 240,
+            interval: 60.0,
+            position_noise: 0.0,
+            gap_probability: 0.0,
+            gap_duration: 1800.0,
+            port: Point { x: 0.0, y: 0.0 },
+            gear: None,
+            seed: 0,
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.nb_of_observations == 0 {
+            return Err(Error::Config(
+                "the number of observations must be greater than 0".into(),
+            ));
+        }
+        if !(self.interval.is_finite() && self.interval > 0.0) {
+            return Err(Error::Config(format!(
+                "interval must be positive, got {}",
+                self.interval
+            )));
+        }
+        if !(self.position_noise.is_finite() && self.position_noise >= 0.0) {
+            return Err(Error::Config(format!(
+                "position noise must be non-negative, got {}",
+                self.position_noise
+            )));
+        }
+        if !(0.0..=1.0).contains(&self.gap_probability) {
+            return Err(Error::Config(format!(
+                "gap probability must be between 0 and 1, got {}",
+                self.gap_probability
+            )));
+        }
+        if !(self.gap_duration.is_finite() && self.gap_duration > 0.0) {
+            return Err(Error::Config(format!(
+                "gap duration must be positive, got {}",
+                self.gap_duration
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Generates labelled trajectories with the Markov graph and the motion
+/// models of the particle filter. The vessels leave the port in the initial
+/// context with a random heading; at every `interval` the context follows a
+/// transition of the graph and the vessel moves with the motion model of its
+/// new context, as the particles do. Records are written at every step except
+/// during the gaps, and labelled with the first label of the context.
+pub struct Simulator {
+    config: SimulationConfig,
+    motion: MotionModel,
+    markov_graph: MarkovGraph<ParticleContextType>,
+}
+
+impl Simulator {
+    pub fn new(
+        config: SimulationConfig,
+        contexts: ContextSet,
+        markov_graph: MarkovGraph<ParticleContextType>,
+    ) -> Simulator {
+        let motion = MotionModel {
+            contexts,
+            home_port: config.port,
+            destination: Destination::Port(config.port),
+        };
+
+        Simulator {
+            config,
+            motion,
+            markov_graph,
+        }
+    }
+
+    /// Ids of the simulated trajectories, `sim1`, `sim2`...
+    pub fn ids(&self) -> Vec<String> {
+        (1..=self.config.nb_of_trajectories)
+            .map(|k| format!("sim{}", k))
+            .collect()
+    }
+
+    /// Simulates one trajectory. Its random stream is derived from the seed
+    /// and the id, so a trajectory does not depend on the others. Fails if a
+    /// context of the graph is not in the context set.
+    pub fn trajectory(&self, id: &str) -> Result<Vec<AisRecord>, Error> {
+        let config = &self.config;
+        let mut rng = ChaCha8Rng::seed_from_u64(derive_seed(config.seed, id));
+
+        let heading = Degrees(random_uniform_range(&mut rng, 0.0, 360.0));
+        let mut vessel = Particle {
+            pos: config.port,
+            direction: Point::from_bearing(heading),
+            heading,
+            turn: Degrees(0.0),
+            speed: 0.0,
+            weight: 1.0,
+            context: self.motion.contexts.initial(),
+            lineage: 0,
+        };
+
+        let mut records: Vec<AisRecord> = Vec::with_capacity(config.nb_of_observations);
+        let mut time = 0.0;
+        let mut steps = 1;
+        while records.len() < config.nb_of_observations {
+            for _ in 0..steps {
+                vessel.context = self
+                    .markov_graph
+                    .sample_dest(&vessel.context, &mut rng)
+                    .unwrap_or(vessel.context);
+                vessel = self.motion.update(&vessel, config.interval, &mut rng)?;
+            }
+            let time_gap = steps as f64 * config.interval;
+            if !records.is_empty() {
+                time += time_gap;
+            }
+
+            let pos = Point {
+                x: vessel.pos.x + random_normal(&mut rng, 0.0, config.position_noise),
+                y: vessel.pos.y + random_normal(&mut rng, 0.0, config.position_noise),
+            };
+            let record = self.record(id, pos, time, time_gap, &vessel, records.last())?;
+            records.push(record);
+
+            steps = if random_uniform(&mut rng) < config.gap_probability {
+                nb_of_steps(config.gap_duration, config.interval)
+            } else {
+                1
+            };
+        }
+
+        Ok(records)
+    }
+
+    /// Record of a position, with the bearing, turn and speed measured from
+    /// the previous record as in the AIS files.
+    fn record(
+        &self,
+        id: &str,
+        pos: Point,
+        time: f64,
+        time_gap: f64,
+        vessel: &Particle,
+        previous: Option<&AisRecord>,
+    ) -> Result<AisRecord, Error> {
+        let (bearing, signed_turn, distance_gap) = match previous {
+            Some(previous) => {
+                let previous_pos = Point {
+                    x: previous.x,
+                    y: previous.y,
+                };
+                let bearing = previous_pos.bearing_to(pos);
+                (
+                    bearing.0,
+                    bearing.difference(Degrees(previous.bearing)).0,
+                    (pos - previous_pos).norm(),
+                )
+            }
+            None => (vessel.heading.0, 0.0, vessel.speed.abs() * time_gap),
+        };
+
+        let context = self.motion.contexts.lookup(&vessel.context)?;
+        let label = context
+            .labels
+            .first()
+            .cloned()
+            .unwrap_or_else(|| context.name.to_string());
+
+        Ok(AisRecord {
+            id: id.to_string(),
+            t: format_time(START_TIME + time.round() as i64),
+            longitude: 0.0,
+            latitude: 0.0,
+            x: pos.x,
+            y: pos.y,
+            signed_turn,
+            bearing,
+            time_gap,
+            distance_gap,
+            euc_speed: distance_gap / time_gap,
+            // The port is on the shore
+            distanceToShore: (pos - self.config.port).norm(),
+            label,
+            gear: self.config.gear.clone(),
+        })
+    }
+}
+
+/// Formats seconds since 1970-01-01 as an ISO 8601 UTC time, e.g.
+/// `2020-01-01T00:01:00`.
+fn format_time(seconds: i64) -> String {
+    let days = seconds.div_euclid(86400);
+    let seconds_of_day = seconds.rem_euclid(86400);
+
+    // Civil date of a day count, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
@@ -0,0 +1,324 @@
+use crate::context::HeadingModel;
+use crate::distribution::{DistributionFamily, SpeedDistribution};
+use crate::error::Error;
+use crate::markov_graph::MarkovGraph;
+use crate::observation::Observation;
+use crate::particle::ParticleContextType;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Context transitions counted over a set of labelled trajectories
+#[derive(Debug, Default)]
+pub struct TransitionStats {
+    /// Number of consecutive observations going from one context to another,
+    /// staying in the same context included
+    counts: BTreeMap<ParticleContextType, BTreeMap<ParticleContextType, u64>>,
+    /// Total time spent in each context, i.e. the sum of the `time_gap` of the
+    /// observations that follow an observation in this context
+    time_in_context: BTreeMap<ParticleContextType, f64>,
+    /// Duration and number of observations of every uninterrupted visit of
+    /// each context
+    visits: BTreeMap<ParticleContextType, Vec<(f64, usize)>>,
+}
+
+/// Statistics of the time spent in a context before switching to another one
+#[derive(Debug, Clone, Copy)]
+pub struct DwellStats {
+    pub nb_of_visits: usize,
+    pub mean_duration: f64,
+    pub min_duration: f64,
+    pub max_duration: f64,
+    pub mean_nb_of_observations: f64,
+}
+
+impl TransitionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the transitions between the labels of one trajectory.
+    pub fn add_trajectory(&mut self, observations: &[Observation]) {
+        let Some(first) = observations.first() else {
+            return;
+        };
+
+        let mut visit_context = first.context.clone();
+        let mut visit = (0.0, 1);
+
+        for pair in observations.windows(2) {
+            let (src, dest) = (&pair[0].context, &pair[1].context);
+
+            *self
+                .counts
+                .entry(src.clone())
+                .or_default()
+                .entry(dest.clone())
+                .or_default() += 1;
+            *self.time_in_context.entry(src.clone()).or_default() += pair[1].time;
+
+            if *dest == visit_context {
+                visit.0 += pair[1].time;
+                visit.1 += 1;
+            } else {
+                self.visits.entry(visit_context).or_default().push(visit);
+                visit_context = dest.clone();
+                visit = (0.0, 1);
+            }
+        }
+
+        self.visits
+            .entry(visit_context.clone())
+            .or_default()
+            .push(visit);
+        // Contexts only seen at the end of a trajectory still need a row
+        self.counts.entry(visit_context).or_default();
+    }
+
+    /// Builds the transition graph. Without `time_step`, the probability of
+    /// going from `i` to `j` is the fraction of observations in `i` followed
+    /// by an observation in `j`. With a `time_step`, the probability of
+    /// leaving `i` is computed from its exit rate per unit of time,
+    /// `1 - exp(-rate * time_step)`, and shared between the destinations in
+    /// proportion of their counts.
+    pub fn to_graph(&self, time_step: Option<f64>) -> MarkovGraph<ParticleContextType> {
+        let mut graph = MarkovGraph::new();
+
+        for (src, dests) in &self.counts {
+            let total: u64 = dests.values().sum();
+            let exits: u64 = dests
+                .iter()
+                .filter(|(dest, _)| *dest != src)
+                .map(|(_, &count)| count)
+                .sum();
+
+            if exits == 0 {
+                graph.add_edge(src.clone(), src.clone(), 1.0);
+                continue;
+            }
+
+            match time_step {
+                None => {
+                    for (dest, &count) in dests {
+                        graph.add_edge(src.clone(), dest.clone(), count as f64 / total as f64);
+                    }
+                }
+                Some(time_step) => {
+                    let time = self.time_in_context.get(src).copied().unwrap_or(0.0);
+                    let leave_prob = if time > 0.0 {
+                        1.0 - (-(exits as f64 / time) * time_step).exp()
+                    } else {
+                        1.0
+                    };
+
+                    graph.add_edge(src.clone(), src.clone(), 1.0 - leave_prob);
+                    for (dest, &count) in dests.iter().filter(|(dest, _)| *dest != src) {
+                        graph.add_edge(
+                            src.clone(),
+                            dest.clone(),
+                            leave_prob * count as f64 / exits as f64,
+                        );
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Dwell time statistics of every context seen in the trajectories.
+    pub fn dwell_stats(&self) -> BTreeMap<ParticleContextType, DwellStats> {
+        self.visits
+            .iter()
+            .map(|(context, visits)| {
+                let nb_of_visits = visits.len();
+                let durations = visits.iter().map(|&(duration, _)| duration);
+                let stats = DwellStats {
+                    nb_of_visits,
+                    mean_duration: durations.clone().sum::<f64>() / nb_of_visits as f64,
+                    min_duration: durations.clone().fold(f64::INFINITY, f64::min),
+                    max_duration: durations.fold(f64::NEG_INFINITY, f64::max),
+                    mean_nb_of_observations: visits.iter().map(|&(_, n)| n).sum::<usize>() as f64
+                        / nb_of_visits as f64,
+                };
+                (context.clone(), stats)
+            })
+            .collect()
+    }
+}
+
+/// Speeds and turns observed in each context of a set of labelled
+/// trajectories
+#[derive(Debug, Default)]
+pub struct MotionSamples {
+    speeds: BTreeMap<ParticleContextType, Vec<f64>>,
+    turns: BTreeMap<ParticleContextType, Vec<f64>>,
+}
+
+/// A speed distribution fitted to samples, with the mean log-likelihood of
+/// the samples it was fitted on
+#[derive(Debug, Clone, Copy)]
+pub struct SpeedFit {
+    pub distribution: SpeedDistribution,
+    pub mean_ln_likelihood: f64,
+}
+
+impl MotionSamples {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the speeds and turns of one trajectory. The first observation is
+    /// skipped since it has no previous observation to compute them from.
+    pub fn add_trajectory(&mut self, observations: &[Observation]) {
+        for observation in observations.iter().skip(1) {
+            if observation.speed.is_finite() {
+                self.speeds
+                    .entry(observation.context.clone())
+                    .or_default()
+                    .push(observation.speed);
+            }
+            if observation.turn.is_finite() {
+                self.turns
+                    .entry(observation.context.clone())
+                    .or_default()
+                    .push(observation.turn);
+            }
+        }
+    }
+
+    pub fn speeds(&self, context: &ParticleContextType) -> &[f64] {
+        self.speeds.get(context).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn turns(&self, context: &ParticleContextType) -> &[f64] {
+        self.turns.get(context).map_or(&[], Vec::as_slice)
+    }
+
+    /// Heading model of a context with the jitter matching its observed
+    /// turns: random walks change the heading uniformly in
+    /// `[-jitter, jitter]`, which has a standard deviation of
+    /// `jitter / sqrt(3)`, and directed models turn by a uniform amount in
+    /// `[0, jitter]`, which has a mean of `jitter / 2`. Circling models turn
+    /// by the mean absolute turn, and the standard deviation of the absolute
+    /// turns gives their jitter.
+    pub fn fit_heading(
+        &self,
+        context: &ParticleContextType,
+        model: HeadingModel,
+    ) -> Option<HeadingModel> {
+        let turns = self.turns(context);
+        let n = turns.len() as f64;
+
+        let jitter = match model {
+            HeadingModel::RandomWalk { .. } => {
+                if turns.len() < 2 {
+                    return None;
+                }
+                let mean = turns.iter().sum::<f64>() / n;
+                let variance =
+                    turns.iter().map(|turn| (turn - mean).powi(2)).sum::<f64>() / (n - 1.0);
+                3.0f64.sqrt() * variance.sqrt()
+            }
+            HeadingModel::AwayFromPort { .. } | HeadingModel::TowardPort { .. } => {
+                if turns.is_empty() {
+                    return None;
+                }
+                2.0 * turns.iter().map(|turn| turn.abs()).sum::<f64>() / n
+            }
+            HeadingModel::Circling { .. } => {
+                if turns.len() < 2 {
+                    return None;
+                }
+                let rate = turns.iter().map(|turn| turn.abs()).sum::<f64>() / n;
+                let variance = turns
+                    .iter()
+                    .map(|turn| (turn.abs() - rate).powi(2))
+                    .sum::<f64>()
+                    / (n - 1.0);
+                return Some(HeadingModel::Circling {
+                    rate,
+                    jitter: 3.0f64.sqrt() * variance.sqrt(),
+                });
+            }
+        };
+
+        Some(model.with_jitter(jitter))
+    }
+}
+
+/// Fits a speed distribution to speed samples. Without a family, every family
+/// is fitted and the one with the highest likelihood is returned. Families
+/// are compared on the positive samples since log-normal and gamma
+/// distributions are not defined at zero.
+pub fn fit_speed(samples: &[f64], family: Option<DistributionFamily>) -> Option<SpeedFit> {
+    let positive_samples: Vec<f64> = samples.iter().copied().filter(|&x| x > 0.0).collect();
+
+    let families = match family {
+        Some(family) => vec![family],
+        None => DistributionFamily::ALL.to_vec(),
+    };
+
+    families
+        .into_iter()
+        .filter_map(|family| SpeedDistribution::fit(family, samples))
+        .map(|distribution| SpeedFit {
+            distribution,
+            mean_ln_likelihood: distribution.mean_ln_likelihood(&positive_samples),
+        })
+        .filter(|fit| fit.mean_ln_likelihood.is_finite())
+        .max_by(|a, b| a.mean_ln_likelihood.total_cmp(&b.mean_ln_likelihood))
+}
+
+/// Writes the dwell time statistics as a CSV file.
+pub fn write_dwell_stats(
+    filename: &str,
+    stats: &BTreeMap<ParticleContextType, DwellStats>,
+) -> Result<(), Error> {
+    write_dwell_stats_csv(filename, stats).map_err(|e| Error::csv(filename, e))
+}
+
+fn write_dwell_stats_csv(
+    filename: &str,
+    stats: &BTreeMap<ParticleContextType, DwellStats>,
+) -> Result<(), csv::Error> {
+    let mut wtr = csv::Writer::from_path(filename)?;
+
+    wtr.write_record([
+        "context",
+        "nb_of_visits",
+        "mean_duration",
+        "min_duration",
+        "max_duration",
+        "mean_nb_of_observations",
+    ])?;
+
+    for (context, stat) in stats {
+        wtr.serialize((
+            context,
+            stat.nb_of_visits,
+            stat.mean_duration,
+            stat.min_duration,
+            stat.max_duration,
+            stat.mean_nb_of_observations,
+        ))?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+impl fmt::Display for DwellStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} visits, duration mean {:.1} (min {:.1}, max {:.1}), {:.1} observations per visit",
+            self.nb_of_visits,
+            self.mean_duration,
+            self.min_duration,
+            self.max_duration,
+            self.mean_nb_of_observations
+        )
+    }
+}
@@ -1,16 +1,48 @@
-use num_traits::Float;
-use rand_distr::num_traits;
+/// Natural logarithm of the gamma function for `x > 0`, computed with the
+/// Lanczos approximation (g = 7, n = 9).
+pub fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
 
-pub fn linspace<T: Float + std::convert::From<u16>>(l: T, h: T, n: usize) -> Vec<T> {
-    let size: T = (n as u16 - 1)
-        .try_into()
-        .expect("too many elements: max is 2^16");
-    let dx = (h - l) / size;
+    if x < 0.5 {
+        // Reflection formula
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
 
-    (1..=n)
-        .scan(-dx, |a, _| {
-            *a = *a + dx;
-            Some(*a)
-        })
-        .collect()
-}
\ No newline at end of file
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(COEFFICIENTS[0], |acc, (i, c)| acc + c / (x + i as f64));
+
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+/// `ln(sum(exp(x)))` without overflow or underflow. Returns the largest value
+/// when it is not finite, e.g. `-inf` when every value is `-inf`.
+pub fn ln_sum_exp<I>(values: I) -> f64
+where
+    I: IntoIterator<Item = f64> + Clone,
+{
+    let max = values.clone().into_iter().fold(f64::NEG_INFINITY, f64::max);
+    if !max.is_finite() {
+        return max;
+    }
+    max + values
+        .into_iter()
+        .map(|x| (x - max).exp())
+        .sum::<f64>()
+        .ln()
+}